@@ -0,0 +1,198 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::clock;
+use color_eyre::eyre::{eyre, Result};
+use hmac::{Hmac, Mac};
+use parking_lot::Mutex;
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{collections::HashMap, fmt, sync::LazyLock};
+use tokio::time::{Duration, Instant};
+
+/// Token url and client id
+type TokenKey = (String, String);
+
+/// Tokens fetched from oauth2 endpoints and when they expire
+static TOKENS: LazyLock<Mutex<HashMap<TokenKey, (String, Instant)>>> =
+    LazyLock::new(Default::default);
+
+/// Refresh tokens this long before they expire
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+/// A secret read on every use, from the first one set of `value`, `file` and `env`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Secret {
+    pub value: String,
+    pub file: String,
+    pub env: String,
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Secret")
+            .field("value", &if self.value.is_empty() { "" } else { "***" })
+            .field("file", &self.file)
+            .field("env", &self.env)
+            .finish()
+    }
+}
+
+impl Secret {
+    pub fn read(&self) -> Result<String> {
+        if !self.value.is_empty() {
+            Ok(self.value.clone())
+        } else if !self.file.is_empty() {
+            Ok(std::fs::read_to_string(&self.file)
+                .map_err(|e| eyre!("read secret file '{}' failed: {}", self.file, e))?
+                .trim()
+                .to_string())
+        } else if !self.env.is_empty() {
+            std::env::var(&self.env)
+                .map_err(|e| eyre!("read secret env '{}' failed: {}", self.env, e))
+        } else {
+            Err(eyre!("secret not configured"))
+        }
+    }
+}
+
+/// How calls to the gateway of a chain are authenticated.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Auth {
+    #[default]
+    None,
+    Bearer {
+        token: Secret,
+    },
+    /// Hex encoded HMAC-SHA256 of the timestamp in ms followed by the body
+    Hmac {
+        key: Secret,
+        #[serde(default = "default_timestamp_header")]
+        timestamp_header: String,
+        #[serde(default = "default_signature_header")]
+        signature_header: String,
+    },
+    Basic {
+        username: String,
+        password: Secret,
+    },
+    /// Bearer token fetched with the oauth2 client credentials grant
+    Oauth2 {
+        token_url: String,
+        client_id: String,
+        client_secret: Secret,
+        #[serde(default)]
+        scope: String,
+    },
+}
+
+fn default_timestamp_header() -> String {
+    "X-Timestamp".to_string()
+}
+
+fn default_signature_header() -> String {
+    "X-Signature".to_string()
+}
+
+impl Auth {
+    /// Authenticate `request`, whose body is `body`.
+    pub async fn apply(
+        &self,
+        http_client: &reqwest::Client,
+        request: RequestBuilder,
+        body: &str,
+    ) -> Result<RequestBuilder> {
+        Ok(match self {
+            Auth::None => request,
+            Auth::Bearer { token } => request.bearer_auth(token.read()?),
+            Auth::Hmac {
+                key,
+                timestamp_header,
+                signature_header,
+            } => {
+                let timestamp = clock::now().to_string();
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.read()?.as_bytes())?;
+                mac.update(timestamp.as_bytes());
+                mac.update(body.as_bytes());
+                request
+                    .header(timestamp_header, timestamp)
+                    .header(signature_header, hex::encode(mac.finalize().into_bytes()))
+            }
+            Auth::Basic { username, password } => {
+                request.basic_auth(username, Some(password.read()?))
+            }
+            Auth::Oauth2 {
+                token_url,
+                client_id,
+                client_secret,
+                scope,
+            } => request.bearer_auth(
+                oauth2_token(http_client, token_url, client_id, client_secret, scope).await?,
+            ),
+        })
+    }
+}
+
+async fn oauth2_token(
+    http_client: &reqwest::Client,
+    token_url: &str,
+    client_id: &str,
+    client_secret: &Secret,
+    scope: &str,
+) -> Result<String> {
+    let key = (token_url.to_string(), client_id.to_string());
+    if let Some((token, expires_at)) = TOKENS.lock().get(&key) {
+        if Instant::now() + TOKEN_REFRESH_MARGIN < *expires_at {
+            return Ok(token.clone());
+        }
+    }
+
+    let mut form = vec![
+        ("grant_type", "client_credentials".to_string()),
+        ("client_id", client_id.to_string()),
+        ("client_secret", client_secret.read()?),
+    ];
+    if !scope.is_empty() {
+        form.push(("scope", scope.to_string()));
+    }
+    let resp = http_client
+        .post(token_url)
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+    let token = resp["access_token"]
+        .as_str()
+        .ok_or_else(|| eyre!("no access_token from '{}'", token_url))?
+        .to_string();
+    let expires_in = resp["expires_in"].as_u64().unwrap_or(300);
+    info!(
+        "fetched oauth2 token from '{}', expires in {}s",
+        token_url, expires_in
+    );
+    TOKENS.lock().insert(
+        key,
+        (
+            token.clone(),
+            Instant::now() + Duration::from_secs(expires_in),
+        ),
+    );
+    Ok(token)
+}
@@ -13,101 +13,326 @@
 // limitations under the License.
 
 use crate::{
-    config::Config,
+    clock::Clock,
+    config::{ChainSender, Config, LivenessProbe, Payload, QueryProbe},
+    finalizer::Watermark,
+    http::HttpClients,
+    load::{self, LoadTestStart},
+    metrics::{
+        BLOCK_HEIGHT, BLOCK_LAG, BLOCK_STALLED, PAYLOAD_LATENCY, PAYLOAD_RESULTS, QUERY_LATENCY,
+    },
+    probe::{as_number, send_tx, verify_tx, Outcome, Probe, QueryCallProbe},
     record::{Record, UnverifiedTX, VerifiedResult},
-    time::{get_latest_finalized_minute, ms_to_minute_scale, unix_now},
+    scenario,
+    template::render,
+    time::ms_to_bucket,
+};
+use parking_lot::{Mutex, RwLock};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use flume::Sender;
-use parking_lot::RwLock;
-use serde_json::{json, Value};
-use std::sync::Arc;
 use storage_dal::Storage;
+use tokio::{task::JoinSet, time::timeout};
 
-pub(crate) struct Client {
+pub(crate) struct Client<P> {
     pub config: Arc<RwLock<Config>>,
     pub storage: Storage,
-    pub http_client: reqwest::Client,
-    pub vr_sender: Sender<VerifiedResult>,
+    pub probe: P,
+    pub http_clients: Arc<HttpClients>,
+    pub clock: Arc<dyn Clock>,
+    pub block_heads: Mutex<HashMap<String, BlockHead>>,
+    /// Scenarios and load tests not finished yet, they are not started again until they are
+    pub running_tasks: Mutex<HashSet<String>>,
+    /// How many payloads have been picked for each chain
+    pub payload_turns: Mutex<HashMap<String, u64>>,
+}
+
+/// The latest block height seen for a chain and when it last advanced.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockHead {
+    pub height: u64,
+    /// Units in ms
+    pub advanced_at: u64,
 }
 
-impl Client {
-    pub async fn sender(&self) {
+impl<P: Probe> Client<P> {
+    pub fn new(
+        config: Arc<RwLock<Config>>,
+        storage: Storage,
+        probe: P,
+        http_clients: Arc<HttpClients>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            config,
+            storage,
+            probe,
+            http_clients,
+            clock,
+            block_heads: Default::default(),
+            running_tasks: Default::default(),
+            payload_turns: Default::default(),
+        }
+    }
+
+    /// Send to `chain_sender` with `user_code`
+    async fn send(&self, chain_sender: &ChainSender, user_code: String) {
+        let (record, utx) = send_tx(
+            &self.probe,
+            self.clock.as_ref(),
+            chain_sender,
+            user_code,
+            self.next_payload(chain_sender),
+            "send",
+        )
+        .await;
+        let outcome = self.probe.classify(&record);
+        PAYLOAD_LATENCY
+            .with_label_values(&[&chain_sender.chain_name, &record.payload, "send"])
+            .observe((self.clock.now() - record.timestamp) as f64 / 1000.0);
+        PAYLOAD_RESULTS
+            .with_label_values(&[
+                &chain_sender.chain_name,
+                &record.payload,
+                if outcome == Outcome::Succeed {
+                    "sent"
+                } else {
+                    "sent_failed"
+                },
+            ])
+            .inc();
+        // save UnverifiedTX
+        if let Some(utx) = utx {
+            debug!("insert: {:?}", &utx);
+            self.storage.insert(&utx.key(), utx);
+        }
+
+        // When the call or decode fails, the sent_failed_num at current_bucket will increase
+        for name in chain_sender.send_names(&record.user_code) {
+            self.update_vr(&name, chain_sender.bucket_secs, record.timestamp, |vr| {
+                if outcome == Outcome::Succeed {
+                    vr.sent_num += 1;
+                    info!("sender insert: {:?}", vr);
+                } else {
+                    vr.sent_failed_num += 1;
+                    warn!("sender insert: {:?}", vr);
+                }
+            });
+        }
+
+        debug!("sender: {:?}", &record);
+    }
+
+    /// Pick the payload of the next send to `chain_sender`
+    pub fn next_payload(&self, chain_sender: &ChainSender) -> Payload {
+        let turn = {
+            let mut payload_turns = self.payload_turns.lock();
+            let turn = payload_turns
+                .entry(chain_sender.chain_name.clone())
+                .or_default();
+            *turn += 1;
+            *turn - 1
+        };
+        chain_sender.pick_payload(turn)
+    }
+
+    pub async fn query(&self) {
         let config = self.config.read().clone();
-        for chain_sender in config.chain_sender_vec {
-            let mut record = Record {
-                timestamp: unix_now(),
-                api: chain_sender.sender_url,
-                data: chain_sender.data_for_send.clone(),
-                resp: json!(null),
-                status: 0,
-                user_code: chain_sender.user_code,
-            };
-            match self
-                .http_client
-                .post(&record.api)
-                .header("Content-Type", "application/json")
-                .header("request_key", record.timestamp.to_string())
-                .header("user_code", &record.user_code)
-                .body(record.data.clone())
-                .send()
-                .await
-            {
-                Ok(resp) => {
-                    debug!("resp: {:?}", resp);
-                    match resp.json::<Value>().await {
-                        Ok(resp) => {
-                            info!("Post '{}': {:?}", &record.api, resp);
-                            record.add_resp(resp.clone());
-                            // save UnverifiedTX
-                            if resp["code"].as_u64().unwrap() == 200 {
-                                let utx = UnverifiedTX {
-                                    tx_hash: resp["data"]["hash"].to_string().replace('\"', ""),
-                                    sent_timestamp: record.timestamp,
-                                    chain_name: chain_sender.chain_name.clone(),
-                                    user_code: record.user_code.clone(),
-                                };
-                                debug!("insert: {:?}", &utx);
-                                self.storage.insert(&utx.key(), utx);
-                            }
+        for chain_sender in &config.chain_sender_vec {
+            for query in &chain_sender.query_probe_vec {
+                let probe = QueryCallProbe {
+                    http_clients: self.http_clients.clone(),
+                    query: query.clone(),
+                };
+                let mut record = Record {
+                    timestamp: self.clock.now(),
+                    api: query.url.clone(),
+                    data: render(&query.body, &chain_sender.template_vars()),
+                    resp: json!(null),
+                    status: 0,
+                    user_code: chain_sender.user_code.clone(),
+                    ..Default::default()
+                };
+                let start = Instant::now();
+                probe.submit(chain_sender, &mut record).await;
+                QUERY_LATENCY
+                    .with_label_values(&[&chain_sender.chain_name, &query.name])
+                    .observe(start.elapsed().as_secs_f64());
+                let outcome = probe.classify(&record);
+
+                self.update_vr(
+                    &chain_sender.query_name(query),
+                    chain_sender.bucket_secs,
+                    record.timestamp,
+                    |vr| {
+                        vr.sent_num += 1;
+                        if outcome == Outcome::Succeed {
+                            vr.succeed_num += 1;
+                            info!("query insert: {:?}", vr);
+                        } else {
+                            vr.failed_num += 1;
+                            warn!("query insert: {:?}", vr);
                         }
-                        Err(e) => error!("decoding resp from '{}' failed: {}", &record.api, e),
-                    }
+                    },
+                );
+                debug!("query: {:?}", &record);
+            }
+        }
+    }
+
+    /// Fetch the latest block of every chain once its liveness `interval` elapsed
+    pub async fn run_liveness(&self) {
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        // Units in ms, when the latest block of each chain was last fetched
+        let mut fetched_at: HashMap<String, u64> = HashMap::new();
+        loop {
+            ticker.tick().await;
+            let config = self.config.read().clone();
+            for chain_sender in &config.chain_sender_vec {
+                let Some(liveness) = &chain_sender.liveness else {
+                    continue;
+                };
+                let now = self.clock.now();
+                if fetched_at
+                    .get(&chain_sender.chain_name)
+                    .is_some_and(|&at| now - at < liveness.interval * 1000)
+                {
+                    continue;
                 }
-                Err(e) => error!("Call '{}' failed: {}", &record.api, e),
+                fetched_at.insert(chain_sender.chain_name.clone(), now);
+                self.liveness(chain_sender, liveness).await;
             }
+        }
+    }
 
-            // When the call or decode fails, the sent_failed_num at current_minute will increase
-            let current_minute = ms_to_minute_scale(record.timestamp);
-            let mut vr = self
-                .storage
-                .get::<VerifiedResult>(&format!("{}/{}", chain_sender.chain_name, current_minute))
-                .unwrap_or_else(|| {
-                    // Record the result of the first two timeout intervals at the current moment
-                    let res = self.storage.get::<VerifiedResult>(&format!(
-                        "{}/{}",
-                        chain_sender.chain_name,
-                        get_latest_finalized_minute(record.timestamp, config.validator_timeout,)
-                    ));
-                    if let Some(res) = res {
-                        let _ = self.vr_sender.send(res);
-                    }
-                    VerifiedResult::new(current_minute, chain_sender.chain_name.clone())
+    async fn liveness(&self, chain_sender: &ChainSender, liveness: &LivenessProbe) {
+        let probe = QueryCallProbe {
+            http_clients: self.http_clients.clone(),
+            query: QueryProbe {
+                url: liveness.url.clone(),
+                body: liveness.body.clone(),
+                pointer: liveness.height_pointer.clone(),
+                numeric: true,
+                ..Default::default()
+            },
+        };
+        let mut record = Record {
+            timestamp: self.clock.now(),
+            api: liveness.url.clone(),
+            data: render(&liveness.body, &chain_sender.template_vars()),
+            resp: json!(null),
+            status: 0,
+            user_code: chain_sender.user_code.clone(),
+            ..Default::default()
+        };
+        probe.submit(chain_sender, &mut record).await;
+        let now = record.timestamp;
+        let height = match probe.classify(&record) {
+            Outcome::Succeed => record
+                .resp
+                .pointer(&liveness.height_pointer)
+                .and_then(as_number),
+            Outcome::Failed => None,
+        };
+
+        let chain_name = &chain_sender.chain_name;
+        let available = match height {
+            Some(height) => {
+                let height = height as u64;
+                let mut block_heads = self.block_heads.lock();
+                let head = block_heads.entry(chain_name.clone()).or_insert(BlockHead {
+                    height,
+                    advanced_at: now,
                 });
-            if record.status == 200 {
-                vr.sent_num += 1;
-                info!("sender insert: {:?}", &vr);
-            } else {
-                vr.sent_failed_num += 1;
-                warn!("sender insert: {:?}", &vr);
+                if height < head.height {
+                    warn!(
+                        "block height of '{}' went down from {} to {}, the chain is reset",
+                        chain_name, head.height, height
+                    );
+                }
+                if height != head.height {
+                    *head = BlockHead {
+                        height,
+                        advanced_at: now,
+                    };
+                }
+                let stalled = now.saturating_sub(head.advanced_at);
+                BLOCK_HEIGHT
+                    .with_label_values(&[chain_name])
+                    .set(height as i64);
+                BLOCK_STALLED
+                    .with_label_values(&[chain_name])
+                    .set(stalled as f64 / 1000.0);
+                if let Some(block_time) = record
+                    .resp
+                    .pointer(&liveness.time_pointer)
+                    .and_then(as_number)
+                {
+                    // Block time in seconds is far below any timestamp in ms
+                    let block_time = if block_time < 1e12 {
+                        block_time * 1000.0
+                    } else {
+                        block_time
+                    };
+                    BLOCK_LAG
+                        .with_label_values(&[chain_name])
+                        .set((now as f64 - block_time) / 1000.0);
+                }
+                stalled <= liveness.stall_window * 1000
             }
-            self.storage.insert(
-                &format!("{}/{}", chain_sender.chain_name, current_minute),
-                vr,
-            );
+            None => false,
+        };
+
+        self.update_vr(
+            &chain_sender.liveness_name(),
+            chain_sender.bucket_secs,
+            now,
+            |vr| {
+                vr.sent_num += 1;
+                if available {
+                    vr.succeed_num += 1;
+                    info!("liveness insert: {:?}", vr);
+                } else {
+                    vr.failed_num += 1;
+                    warn!("liveness insert: {:?}", vr);
+                }
+            },
+        );
+        debug!("liveness: {:?}", &record);
+    }
 
-            debug!("sender: {:?}", &record);
-            // self.storage.insert(&current_minute.to_string(), record);
+    /// Update the `VerifiedResult` of `name` at the bucket of `timestamp`,
+    /// unless the bucket is counted already.
+    fn update_vr(
+        &self,
+        name: &str,
+        bucket_secs: u64,
+        timestamp: u64,
+        update: impl FnOnce(&mut VerifiedResult),
+    ) {
+        let current_bucket = ms_to_bucket(timestamp, bucket_secs);
+        // Counted buckets are never changed, or recovery would count them differently
+        if self
+            .storage
+            .get::<Watermark>(name)
+            .is_some_and(|watermark| (current_bucket + 1) * bucket_secs <= watermark.end_secs())
+        {
+            warn!(
+                "{} at bucket {} is finalized already, the result is dropped",
+                name, current_bucket
+            );
+            return;
         }
+        let key = format!("{}/{}", name, current_bucket);
+        let mut vr = self
+            .storage
+            .get::<VerifiedResult>(&key)
+            .unwrap_or_else(|| VerifiedResult::new(current_bucket, bucket_secs, name.to_string()));
+        update(&mut vr);
+        self.storage.insert(&key, vr);
     }
 
     pub async fn validator(&self) {
@@ -118,74 +343,496 @@ impl Client {
                 .storage
                 .get_by_path::<UnverifiedTX>(unverified_path.unwrap().path())
                 .unwrap();
-            let current_minute = ms_to_minute_scale(utx.sent_timestamp);
-            let mut vr = self
-                .storage
-                .get::<VerifiedResult>(&format!("{}/{}", utx.chain_name, current_minute))
-                .unwrap_or_else(|| VerifiedResult::new(current_minute, utx.chain_name.clone()));
-            if unix_now() - utx.sent_timestamp > (config.validator_timeout * 1000) {
+            // The chain may have been removed since the tx was sent
+            let chain_sender = config
+                .chain_sender_vec
+                .iter()
+                .find(|chain_sender| chain_sender.chain_name == utx.chain_name)
+                .cloned()
+                .unwrap_or_else(|| ChainSender {
+                    chain_name: utx.chain_name.clone(),
+                    ..Default::default()
+                });
+            if self.clock.now() - utx.sent_timestamp > (config.validator_timeout * 1000) {
                 // timeout and failed
                 warn!("Failed: {:?}", &utx.tx_hash);
                 self.storage.remove::<UnverifiedTX>(&utx.key());
+                PAYLOAD_RESULTS
+                    .with_label_values(&[&utx.chain_name, &utx.payload, "failed"])
+                    .inc();
 
-                vr.failed_num += 1;
-                warn!("validator insert: {:?}", &vr);
-                self.storage
-                    .insert(&format!("{}/{}", utx.chain_name, current_minute), vr);
+                self.update_sent_vr(&chain_sender, &utx, |vr| {
+                    vr.failed_num += 1;
+                    warn!("validator insert: {:?}", vr);
+                });
                 continue;
             }
 
-            self.verify_from_api(utx, vr, current_minute, &config.verify_api_url)
-                .await;
+            self.verify_from_api(&chain_sender, utx, &config).await;
+        }
+    }
+
+    /// Update the `VerifiedResult`s `utx` is reported to, at the bucket it was sent.
+    fn update_sent_vr(
+        &self,
+        chain_sender: &ChainSender,
+        utx: &UnverifiedTX,
+        update: impl Fn(&mut VerifiedResult),
+    ) {
+        for name in chain_sender.send_names(&utx.user_code) {
+            self.update_vr(&name, chain_sender.bucket_secs, utx.sent_timestamp, &update);
         }
     }
 
     async fn verify_from_api(
         &self,
+        chain_sender: &ChainSender,
         utx: UnverifiedTX,
-        mut vr: VerifiedResult,
-        current_minute: u64,
-        verify_api_url: &str,
+        config: &Config,
     ) {
-        let mut record = Record {
-            timestamp: unix_now(),
-            api: verify_api_url.to_string(),
-            resp: json!(null),
-            status: 0,
-            user_code: utx.user_code.clone(),
+        let record = verify_tx(
+            &self.probe,
+            self.clock.as_ref(),
+            chain_sender,
+            &utx,
+            &config.verify_api_url,
+            "verify",
+        )
+        .await;
+
+        if self.probe.classify(&record) == Outcome::Succeed {
+            info!("Success: {:?}", &utx.tx_hash);
+            self.storage.remove::<UnverifiedTX>(&utx.key());
+            PAYLOAD_RESULTS
+                .with_label_values(&[&utx.chain_name, &utx.payload, "succeed"])
+                .inc();
+            PAYLOAD_LATENCY
+                .with_label_values(&[&utx.chain_name, &utx.payload, "inclusion"])
+                .observe((self.clock.now() - utx.sent_timestamp) as f64 / 1000.0);
+
+            self.update_sent_vr(chain_sender, &utx, |vr| {
+                vr.succeed_num += 1;
+                if utx.retried || record.attempts > 1 {
+                    vr.retried_num += 1;
+                }
+                info!("validator insert: {:?}", vr);
+            });
+        }
+
+        debug!("verify: {:?}", &record);
+    }
+}
+
+impl<P: Probe + Send + Sync + 'static> Client<P> {
+    /// Send to every chain at once, so the retries of one chain do not
+    /// delay the others
+    pub async fn sender(self: &Arc<Self>) {
+        let config = self.config.read().clone();
+        let mut sends = JoinSet::new();
+        for chain_sender in config.chain_sender_vec {
+            let client = self.clone();
+            sends.spawn(async move {
+                for user_code in chain_sender.user_codes() {
+                    client.send(&chain_sender, user_code).await;
+                }
+            });
+        }
+        while let Some(result) = sends.join_next().await {
+            if let Err(e) = result {
+                warn!("sender task failed: {}", e);
+            }
+        }
+    }
+
+    /// Spawn a run of every scenario, they may wait for receipts far longer
+    /// than `sender_interval`.
+    pub fn scenario(self: &Arc<Self>) {
+        let config = self.config.read().clone();
+        for chain_sender in config.chain_sender_vec {
+            for scenario in &chain_sender.scenario_vec {
+                let name = chain_sender.scenario_name(scenario);
+                if !self.running_tasks.lock().insert(name.clone()) {
+                    debug!("scenario '{}' is still running", name);
+                    continue;
+                }
+                let client = self.clone();
+                let chain_sender = chain_sender.clone();
+                let scenario = scenario.clone();
+                let validator_timeout = config.validator_timeout;
+                tokio::spawn(async move {
+                    let timestamp = client.clock.now();
+                    // Its bucket may be finalized once it runs longer
+                    let passed = match timeout(
+                        Duration::from_secs(validator_timeout),
+                        scenario::run(&client.http_clients, &chain_sender, &scenario),
+                    )
+                    .await
+                    {
+                        Ok(passed) => passed,
+                        Err(_) => {
+                            warn!("scenario '{}' timed out after {}s", name, validator_timeout);
+                            false
+                        }
+                    };
+                    client.update_vr(&name, chain_sender.bucket_secs, timestamp, |vr| {
+                        vr.sent_num += 1;
+                        if passed {
+                            vr.succeed_num += 1;
+                            info!("scenario insert: {:?}", vr);
+                        } else {
+                            vr.failed_num += 1;
+                            warn!("scenario insert: {:?}", vr);
+                        }
+                    });
+                    client.running_tasks.lock().remove(&name);
+                });
+            }
+        }
+    }
+
+    /// Spawn the load tests that are due
+    pub fn load_test(self: &Arc<Self>) {
+        let config = self.config.read().clone();
+        let now = self.clock.now();
+        for chain_sender in config.chain_sender_vec {
+            let Some(load) = chain_sender.load_test.clone() else {
+                continue;
+            };
+            let name = format!("load/{}", chain_sender.chain_name);
+            // Stored so restarts don't put the next run off, the first one runs right away
+            if self
+                .storage
+                .get::<LoadTestStart>(&chain_sender.chain_name)
+                .is_some_and(|start| now.saturating_sub(start.timestamp) < load.interval * 1000)
+            {
+                continue;
+            }
+            if !self.running_tasks.lock().insert(name.clone()) {
+                debug!(
+                    "load test of '{}' is still running",
+                    chain_sender.chain_name
+                );
+                continue;
+            }
+            self.storage.insert(
+                &chain_sender.chain_name,
+                LoadTestStart {
+                    chain_name: chain_sender.chain_name.clone(),
+                    timestamp: now,
+                },
+            );
+            let client = self.clone();
+            let verify_api_url = config.verify_api_url.clone();
+            let validator_timeout = config.validator_timeout;
+            tokio::spawn(async move {
+                load::run(
+                    client.clone(),
+                    chain_sender,
+                    load,
+                    verify_api_url,
+                    validator_timeout,
+                )
+                .await;
+                client.running_tasks.lock().remove(&name);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        clock::SimulatedClock, config::RetryPolicy, finalizer::finalize, metrics::Counting,
+        probe::FakeProbe,
+    };
+
+    /// A storage of its own for every test
+    pub(crate) fn storage() -> Storage {
+        Storage::init_sled(
+            &std::env::temp_dir()
+                .join(format!("sla-client-test-{}", uuid::Uuid::new_v4()))
+                .to_string_lossy(),
+        )
+    }
+
+    pub(crate) fn chain_sender(chain_name: &str) -> ChainSender {
+        ChainSender {
+            chain_name: chain_name.to_string(),
+            sender_url: "http://gateway/send_tx".to_string(),
+            data_for_send: "{}".to_string(),
+            user_code: "0".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A client of `chain_sender` whose clock stands at `now`
+    pub(crate) fn client(chain_sender: &ChainSender, now: u64) -> Client<FakeProbe> {
+        let config = Config {
+            chain_sender_vec: vec![chain_sender.clone()],
             ..Default::default()
         };
+        Client::new(
+            Arc::new(RwLock::new(config)),
+            storage(),
+            FakeProbe::default(),
+            Arc::default(),
+            Arc::new(SimulatedClock::new(now, 0.0)),
+        )
+    }
 
-        match self
-            .http_client
-            .get(&record.api)
-            .header("request_key", utx.sent_timestamp.to_string())
-            .header("user_code", &record.user_code)
-            .send()
-            .await
-        {
-            Ok(resp) => match resp.json::<Value>().await {
-                Ok(resp) => {
-                    info!("Get  '{}/{}': {:?}", &record.api, &record.data, resp);
-                    record.add_resp(resp);
-                }
-                Err(e) => error!("decoding resp from '{}' failed: {}", &record.api, e),
+    fn vr(client: &Client<FakeProbe>, name: &str, now: u64) -> VerifiedResult {
+        client
+            .storage
+            .get(&format!("{}/{}", name, ms_to_bucket(now, 60)))
+            .unwrap()
+    }
+
+    fn utxs(client: &Client<FakeProbe>) -> usize {
+        client.storage.scan::<UnverifiedTX>().len()
+    }
+
+    const NOW: u64 = 1_700_000_005_000;
+
+    #[tokio::test]
+    async fn send_stores_the_tx_to_verify() {
+        let chain_sender = chain_sender("send-ok");
+        let client = client(&chain_sender, NOW);
+        client.send(&chain_sender, "0".to_string()).await;
+
+        let vr = vr(&client, "send-ok", NOW);
+        assert_eq!((vr.sent_num, vr.sent_failed_num), (1, 0));
+        assert_eq!(utxs(&client), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_send_is_sent_failed() {
+        let chain_sender = chain_sender("send-failed");
+        let client = client(&chain_sender, NOW);
+        client.probe.submit_statuses.lock().push_back(500);
+        client.send(&chain_sender, "0".to_string()).await;
+
+        let vr = vr(&client, "send-failed", NOW);
+        assert_eq!((vr.sent_num, vr.sent_failed_num), (0, 1));
+        assert_eq!(utxs(&client), 0);
+    }
+
+    #[tokio::test]
+    async fn sends_of_tenants_are_reported_to_both_series() {
+        let chain_sender = ChainSender {
+            user_code_vec: vec!["a".to_string(), "b".to_string()],
+            ..chain_sender("send-tenants")
+        };
+        let client = Arc::new(client(&chain_sender, NOW));
+        client.sender().await;
+
+        assert_eq!(vr(&client, "send-tenants", NOW).sent_num, 2);
+        assert_eq!(vr(&client, "send-tenants-user-a", NOW).sent_num, 1);
+        assert_eq!(vr(&client, "send-tenants-user-b", NOW).sent_num, 1);
+    }
+
+    #[tokio::test]
+    async fn verified_tx_is_succeed() {
+        let chain_sender = chain_sender("verify-ok");
+        let client = client(&chain_sender, NOW);
+        client.send(&chain_sender, "0".to_string()).await;
+        client.validator().await;
+
+        let vr = vr(&client, "verify-ok", NOW);
+        assert_eq!((vr.sent_num, vr.succeed_num, vr.failed_num), (1, 1, 0));
+        assert_eq!(utxs(&client), 0);
+    }
+
+    #[tokio::test]
+    async fn unverified_tx_is_kept_until_it_times_out() {
+        let chain_sender = chain_sender("verify-pending");
+        let client = client(&chain_sender, NOW);
+        client.send(&chain_sender, "0".to_string()).await;
+        client.probe.verify_statuses.lock().push_back(404);
+        client.validator().await;
+
+        let vr = vr(&client, "verify-pending", NOW);
+        assert_eq!((vr.sent_num, vr.succeed_num, vr.failed_num), (1, 0, 0));
+        assert_eq!(utxs(&client), 1);
+    }
+
+    /// Retried on a connect error without backoff
+    fn retrying(chain_name: &str) -> ChainSender {
+        ChainSender {
+            retry: RetryPolicy {
+                max_attempts: 3,
+                backoff: 0,
+                ..Default::default()
             },
-            Err(e) => error!("Call '{}' failed: {}", &record.api, e),
+            ..chain_sender(chain_name)
         }
+    }
 
-        if record.status == 200 {
-            info!("Success: {:?}", &utx.tx_hash);
-            self.storage.remove::<UnverifiedTX>(&utx.key());
+    #[tokio::test]
+    async fn retried_tx_is_counted_once_when_it_succeeds() {
+        let chain_sender = retrying("retried-ok");
+        let client = client(&chain_sender, NOW);
+        client.probe.submit_statuses.lock().push_back(500);
+        client.send(&chain_sender, "0".to_string()).await;
+        client.probe.verify_statuses.lock().push_back(500);
+        client.validator().await;
+
+        let vr = vr(&client, "retried-ok", NOW);
+        assert_eq!((vr.sent_num, vr.succeed_num, vr.retried_num), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn retried_tx_is_not_counted_until_it_succeeds() {
+        let chain_sender = retrying("retried-pending");
+        let client = client(&chain_sender, NOW);
+        client.probe.submit_statuses.lock().push_back(500);
+        client.send(&chain_sender, "0".to_string()).await;
+        client.probe.verify_statuses.lock().extend([404, 404, 404]);
+        client.validator().await;
+
+        let vr = vr(&client, "retried-pending", NOW);
+        assert_eq!((vr.sent_num, vr.succeed_num, vr.retried_num), (1, 0, 0));
+        assert_eq!(utxs(&client), 1);
+    }
+
+    #[tokio::test]
+    async fn result_of_a_counted_bucket_is_dropped() {
+        let chain_sender = chain_sender("counted");
+        let client = client(&chain_sender, NOW);
+        client.storage.insert(
+            "counted",
+            Watermark {
+                name: "counted".to_string(),
+                bucket: ms_to_bucket(NOW, 60),
+                bucket_secs: 60,
+            },
+        );
+        client.send(&chain_sender, "0".to_string()).await;
+        // e.g. a scenario finished after its bucket is counted
+        client.update_vr("counted", 60, NOW, |vr| vr.succeed_num += 1);
+
+        assert!(client
+            .storage
+            .get::<VerifiedResult>(&format!("counted/{}", ms_to_bucket(NOW, 60)))
+            .is_none());
+    }
+
+    /// A client on a clock advanced by hand, whose finalized buckets are counted
+    struct Pipeline {
+        client: Client<FakeProbe>,
+        clock: Arc<SimulatedClock>,
+        counting: Counting,
+        vr_sender: flume::Sender<VerifiedResult>,
+        vr_receiver: flume::Receiver<VerifiedResult>,
+        emitted: HashMap<String, u64>,
+    }
 
-            vr.succeed_num += 1;
-            info!("validator insert: {:?}", &vr);
-            self.storage
-                .insert(&format!("{}/{}", utx.chain_name, current_minute), vr);
+    impl Pipeline {
+        /// Started at `NOW`, with the buckets before finalized already
+        fn new(chain_sender: &ChainSender) -> Self {
+            let clock = Arc::new(SimulatedClock::new(NOW, 0.0));
+            let config = Config {
+                chain_sender_vec: vec![chain_sender.clone()],
+                ..Default::default()
+            };
+            let client = Client::new(
+                Arc::new(RwLock::new(config)),
+                storage(),
+                FakeProbe::default(),
+                Arc::default(),
+                clock.clone(),
+            );
+            let (vr_sender, vr_receiver) = flume::unbounded();
+            let mut pipeline = Self {
+                counting: Counting::new(client.storage.clone(), false),
+                client,
+                clock,
+                vr_sender,
+                vr_receiver,
+                emitted: HashMap::new(),
+            };
+            pipeline
+                .counting
+                .add_configured(&pipeline.client.config.read(), NOW);
+            pipeline
         }
 
-        debug!("verify: {:?}", &record);
-        // self.storage
-        //     .insert(&format!("{}", &record.timestamp), record);
+        /// Finalize and count the buckets older than `validator_timeout`
+        fn finalize(&mut self) {
+            let config = self.client.config.read().clone();
+            let now = self.clock.now();
+            finalize(
+                &self.client.storage,
+                &config,
+                &self.vr_sender,
+                now,
+                &mut self.emitted,
+            );
+            for vr in self.vr_receiver.drain() {
+                self.counting.count(&config, &vr, now);
+            }
+        }
+
+        /// Units in ms, after which the bucket of `NOW` is finalized
+        fn past_validator_timeout(&self) -> u64 {
+            (self.client.config.read().validator_timeout + 120) * 1000
+        }
+    }
+
+    #[tokio::test]
+    async fn verified_tx_is_counted_available() {
+        let chain_sender = chain_sender("pipeline-ok");
+        let mut pipeline = Pipeline::new(&chain_sender);
+        pipeline.client.send(&chain_sender, "0".to_string()).await;
+        pipeline.clock.advance(1000);
+        pipeline.client.validator().await;
+        pipeline.clock.advance(pipeline.past_validator_timeout());
+        pipeline.finalize();
+
+        let (observed, unavailable, _) = pipeline.counting.counted("pipeline-ok");
+        assert_eq!((observed, unavailable), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn tx_not_included_in_time_is_counted_unavailable() {
+        let chain_sender = chain_sender("pipeline-timeout");
+        let mut pipeline = Pipeline::new(&chain_sender);
+        pipeline.client.send(&chain_sender, "0".to_string()).await;
+        pipeline.client.probe.verify_statuses.lock().push_back(404);
+        pipeline.client.validator().await;
+        pipeline.clock.advance(pipeline.past_validator_timeout());
+        pipeline.client.validator().await;
+        pipeline.finalize();
+
+        let (observed, unavailable, _) = pipeline.counting.counted("pipeline-timeout");
+        assert_eq!((observed, unavailable), (1, 1));
+        assert_eq!(utxs(&pipeline.client), 0);
+    }
+
+    #[tokio::test]
+    async fn bucket_is_not_counted_before_validator_timeout() {
+        let chain_sender = chain_sender("pipeline-pending");
+        let mut pipeline = Pipeline::new(&chain_sender);
+        pipeline.client.send(&chain_sender, "0".to_string()).await;
+        pipeline.clock.advance(120_000);
+        pipeline.finalize();
+        assert_eq!(pipeline.counting.counted("pipeline-pending").0, 0);
+
+        pipeline.client.validator().await;
+        pipeline.clock.advance(pipeline.past_validator_timeout());
+        pipeline.finalize();
+        assert_eq!(pipeline.counting.counted("pipeline-pending").0, 1);
+    }
+
+    #[tokio::test]
+    async fn bucket_without_probes_is_no_data() {
+        let chain_sender = chain_sender("pipeline-idle");
+        let mut pipeline = Pipeline::new(&chain_sender);
+        pipeline.clock.advance(pipeline.past_validator_timeout());
+        pipeline.finalize();
+
+        let (observed, _, no_data) = pipeline.counting.counted("pipeline-idle");
+        assert_eq!(observed, 0);
+        assert!(no_data > 0);
     }
 }
@@ -0,0 +1,124 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::time::unix_now;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Instant,
+};
+
+/// The clock of the helpers without a `Clock` at hand
+static INSTALLED: OnceLock<Arc<dyn Clock>> = OnceLock::new();
+
+/// Make `clock` the one of `now`, once at start
+pub fn install(clock: Arc<dyn Clock>) {
+    if INSTALLED.set(clock).is_err() {
+        warn!("a clock is installed already");
+    }
+}
+
+/// Units in ms, the time of the installed clock, the system time if none is
+pub fn now() -> u64 {
+    INSTALLED.get().map_or_else(unix_now, |clock| clock.now())
+}
+
+/// Source of the current time for sending, bucketing and finalization,
+/// installed for the templates, signatures and skew measurements.
+pub trait Clock: Debug + Send + Sync {
+    /// Units in ms
+    fn now(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        unix_now()
+    }
+}
+
+/// Starts at `start` and runs `speed` times as fast as the system clock.
+#[derive(Debug)]
+pub struct SimulatedClock {
+    /// Units in ms
+    start: u64,
+    speed: f64,
+    started: Instant,
+    /// Units in ms, jumped ahead by `advance`
+    advanced: AtomicU64,
+}
+
+impl SimulatedClock {
+    pub fn new(start: u64, speed: f64) -> Self {
+        Self {
+            start,
+            speed,
+            started: Instant::now(),
+            advanced: AtomicU64::new(0),
+        }
+    }
+
+    /// Jump `ms` ahead
+    #[cfg(test)]
+    pub fn advance(&self, ms: u64) {
+        self.advanced.fetch_add(ms, Ordering::Relaxed);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> u64 {
+        self.start
+            + self.advanced.load(Ordering::Relaxed)
+            + (self.started.elapsed().as_millis() as f64 * self.speed) as u64
+    }
+}
+
+/// Which clock the client runs on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ClockConfig {
+    #[default]
+    System,
+    /// For rehearsing bucketing, timeouts and recovery against a staging
+    /// gateway without waiting in real time
+    Simulated {
+        /// Units in ms, the system time if 0
+        #[serde(default)]
+        start: u64,
+        #[serde(default = "default_speed")]
+        speed: f64,
+    },
+}
+
+impl ClockConfig {
+    pub fn build(&self) -> Arc<dyn Clock> {
+        match self {
+            Self::System => Arc::new(SystemClock),
+            Self::Simulated { start, speed } => {
+                let start = if *start == 0 { unix_now() } else { *start };
+                Arc::new(SimulatedClock::new(start, *speed))
+            }
+        }
+    }
+}
+
+const fn default_speed() -> f64 {
+    1.0
+}
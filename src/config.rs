@@ -12,20 +12,451 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::{
+    auth::Auth,
+    clock::ClockConfig,
+    overrides::Overrides,
+    record::{ErrorKind, Record},
+    template::render,
+};
+use chrono_tz::Tz;
 use cloud_util::tracer::LogConfig;
+use color_eyre::eyre::{eyre, Result};
+use common_rs::configure::{config_hot_reload, file_config};
+use heck::ToSnakeCase;
+use parking_lot::RwLock;
+use rand::Rng;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::time::interval;
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-#[serde(default)]
+/// Units in second, a day, the largest bucket
+pub const MAX_BUCKET_SECS: u64 = 86_400;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ChainSender {
     pub chain_name: String,
     pub sender_url: String,
+    /// Rendered as a template on every send
     pub data_for_send: String,
+    /// Sent instead of `data_for_send` if not empty
+    pub payload_vec: Vec<Payload>,
+    pub payload_selection: PayloadSelection,
     pub user_code: String,
+    /// Send with each of them instead of `user_code` if not empty, every
+    /// user code is reported as its own tenant series
+    pub user_code_vec: Vec<String>,
+    /// How the chain series is decided from the tenant series
+    pub tenant_rule: TenantRule,
+    /// Applied to every series of this chain
+    pub availability: AvailabilityRule,
+    /// Units in second, the size of the buckets availability is decided in,
+    /// at most `MAX_BUCKET_SECS`
+    pub bucket_secs: u64,
+    /// IANA name, the global `timezone` if not set
+    pub timezone: Option<Tz>,
+    /// Applied to every call to the gateway of this chain
+    pub auth: Auth,
+    pub http: HttpConfig,
+    pub tls: TlsConfig,
+    /// Applied to send and verify calls
+    pub retry: RetryPolicy,
+    pub load_test: Option<LoadTest>,
+    pub query_probe_vec: Vec<QueryProbe>,
+    pub liveness: Option<LivenessProbe>,
+    pub scenario_vec: Vec<Scenario>,
+}
+
+impl ChainSender {
+    /// Variables available to the templates of this chain
+    pub fn template_vars(&self) -> HashMap<String, String> {
+        HashMap::from([("chain_name".to_string(), self.chain_name.clone())])
+    }
+
+    /// Payloads to send, `data_for_send` named "default" if `payload_vec` is empty
+    pub fn payloads(&self) -> Vec<Payload> {
+        if self.payload_vec.is_empty() {
+            vec![Payload {
+                name: "default".to_string(),
+                data: self.data_for_send.clone(),
+                weight: 1,
+            }]
+        } else {
+            self.payload_vec.clone()
+        }
+    }
+
+    /// Pick the payload of the `turn`th send
+    pub fn pick_payload(&self, turn: u64) -> Payload {
+        let mut payloads = self.payloads();
+        match self.payload_selection {
+            PayloadSelection::RoundRobin => {
+                let len = payloads.len() as u64;
+                payloads.swap_remove((turn % len) as usize)
+            }
+            PayloadSelection::Weighted => {
+                let total = payloads.iter().map(|payload| payload.weight).sum::<u64>();
+                let mut point = rand::thread_rng().gen_range(0..total.max(1));
+                let index = payloads
+                    .iter()
+                    .position(|payload| {
+                        let picked = point < payload.weight;
+                        point = point.saturating_sub(payload.weight);
+                        picked
+                    })
+                    .unwrap_or_default();
+                payloads.swap_remove(index)
+            }
+        }
+    }
+
+    /// User codes to send with, `user_code` if `user_code_vec` is empty
+    pub fn user_codes(&self) -> Vec<String> {
+        if self.user_code_vec.is_empty() {
+            vec![self.user_code.clone()]
+        } else {
+            self.user_code_vec.clone()
+        }
+    }
+
+    /// Name of the `VerifiedResult` series of the sends with `user_code`
+    pub fn tenant_name(&self, user_code: &str) -> String {
+        format!("{}-user-{}", self.chain_name, user_code)
+    }
+
+    /// Names of the series a send with `user_code` is reported to
+    pub fn send_names(&self, user_code: &str) -> Vec<String> {
+        let mut names = vec![self.chain_name.clone()];
+        if !self.user_code_vec.is_empty() {
+            names.push(self.tenant_name(user_code));
+        }
+        names
+    }
+
+    /// Names of the tenant series, empty if `user_code_vec` is empty
+    pub fn tenant_names(&self) -> Vec<String> {
+        self.user_code_vec
+            .iter()
+            .map(|user_code| self.tenant_name(user_code))
+            .collect()
+    }
+
+    /// Names of every `VerifiedResult` series of this chain
+    pub fn series_names(&self) -> Vec<String> {
+        std::iter::once(self.chain_name.clone())
+            .chain(self.tenant_names())
+            .chain(
+                self.query_probe_vec
+                    .iter()
+                    .map(|query| self.query_name(query)),
+            )
+            .chain(
+                self.scenario_vec
+                    .iter()
+                    .map(|scenario| self.scenario_name(scenario)),
+            )
+            .chain(self.liveness.as_ref().map(|_| self.liveness_name()))
+            .collect()
+    }
+
+    /// Name of the `VerifiedResult` series of `query`
+    pub fn query_name(&self, query: &QueryProbe) -> String {
+        format!("{}-{}", self.chain_name, query.name)
+    }
+
+    /// Name of the `VerifiedResult` series of `scenario`
+    pub fn scenario_name(&self, scenario: &Scenario) -> String {
+        format!("{}-{}", self.chain_name, scenario.name)
+    }
+
+    /// Name of the `VerifiedResult` series of the block liveness probe
+    pub fn liveness_name(&self) -> String {
+        format!("{}-liveness", self.chain_name)
+    }
+}
+
+impl Default for ChainSender {
+    fn default() -> Self {
+        Self {
+            chain_name: Default::default(),
+            sender_url: Default::default(),
+            data_for_send: Default::default(),
+            payload_vec: Default::default(),
+            payload_selection: Default::default(),
+            user_code: Default::default(),
+            user_code_vec: Default::default(),
+            tenant_rule: Default::default(),
+            availability: Default::default(),
+            bucket_secs: 60,
+            timezone: None,
+            auth: Default::default(),
+            http: Default::default(),
+            tls: Default::default(),
+            retry: Default::default(),
+            load_test: Default::default(),
+            query_probe_vec: Default::default(),
+            liveness: Default::default(),
+            scenario_vec: Default::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Payload {
+    pub name: String,
+    /// Rendered as a template on every send
+    pub data: String,
+    /// Only used by `PayloadSelection::Weighted`
+    pub weight: u64,
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self {
+            name: Default::default(),
+            data: Default::default(),
+            weight: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadSelection {
+    #[default]
+    Weighted,
+    RoundRobin,
+}
+
+/// How many tenants have to be available for their chain to be
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantRule {
+    #[default]
+    All,
+    Any,
+    Quorum(usize),
+}
+
+impl TenantRule {
+    pub const fn is_met(&self, available: usize, total: usize) -> bool {
+        match self {
+            Self::All => available == total,
+            Self::Any => available > 0,
+            Self::Quorum(quorum) => available >= *quorum,
+        }
+    }
+}
+
+/// Decide whether a bucket is available from its `VerifiedResult`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AvailabilityRule {
+    /// Of the succeed probes to all of them
+    pub min_success_ratio: f64,
+    /// Buckets with fewer probes are classified by `sparse_minute`
+    pub min_samples: u64,
+    pub sparse_minute: MinutePolicy,
+    /// Buckets without any `VerifiedResult`
+    pub no_data: MinutePolicy,
+}
+
+impl Default for AvailabilityRule {
+    fn default() -> Self {
+        Self {
+            min_success_ratio: 1.0,
+            min_samples: 1,
+            sparse_minute: MinutePolicy::Excluded,
+            no_data: MinutePolicy::Excluded,
+        }
+    }
+}
+
+/// How a bucket is counted when it can't be judged by its probes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MinutePolicy {
+    Available,
+    Unavailable,
+    /// Neither observed nor unavailable
+    #[default]
+    Excluded,
+}
+
+impl MinutePolicy {
+    /// Whether the bucket is available and strictly available, `None` if it is excluded
+    pub const fn classify(&self) -> Option<(bool, bool)> {
+        match self {
+            Self::Available => Some((true, true)),
+            Self::Unavailable => Some((false, false)),
+            Self::Excluded => None,
+        }
+    }
+}
+
+/// A scheduled run sending the payloads at a ramped up rate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoadTest {
+    /// Units in second, between the starts of two runs
+    pub interval: u64,
+    pub target_tps: f64,
+    /// Units in second
+    pub ramp_up: u64,
+    /// Units in second
+    pub steady: u64,
+    /// Units in second
+    pub ramp_down: u64,
+    /// Run summaries are written here as JSON if not empty
+    pub summary_dir: String,
+    /// Txs sent and not verified yet, sends wait beyond it
+    pub max_in_flight: usize,
+}
+
+impl Default for LoadTest {
+    fn default() -> Self {
+        Self {
+            interval: 86400,
+            target_tps: 10.0,
+            ramp_up: 60,
+            steady: 300,
+            ramp_down: 60,
+            summary_dir: Default::default(),
+            max_in_flight: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Including the first attempt, 1 disables retry
+    pub max_attempts: u8,
+    /// Units in ms, doubled after every retry
+    pub backoff: u64,
+    pub retry_on: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: 500,
+            retry_on: vec![ErrorKind::Connect, ErrorKind::Timeout],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Units in ms, the longest every attempt and backoff of a call may take,
+    /// `http_timeout` is in second
+    pub fn max_duration(&self, http_timeout: u64) -> u64 {
+        let backoffs: u64 = (1..u64::from(self.max_attempts))
+            .map(|attempt| self.backoff << (attempt - 1).min(16))
+            .sum();
+        u64::from(self.max_attempts) * http_timeout * 1000 + backoffs
+    }
+
+    /// The backoff before retrying `record`, if it should be retried
+    pub fn backoff(&self, record: &Record) -> Option<Duration> {
+        match record.error {
+            Some(error)
+                if record.attempts < self.max_attempts && self.retry_on.contains(&error) =>
+            {
+                Some(Duration::from_millis(
+                    self.backoff << (record.attempts - 1).min(16),
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Settings of the http client of a chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Units in second
+    pub connect_timeout: u64,
+    /// Units in second
+    pub timeout: u64,
+    /// Proxy of every request, e.g. "http://127.0.0.1:3128"
+    pub proxy: String,
+    /// Added to every request
+    pub headers: BTreeMap<String, String>,
+    /// Talk HTTP/2 without negotiating it
+    pub http2_prior_knowledge: bool,
+    /// Reuse connections between requests
+    pub keep_alive: bool,
+    /// Units in second
+    pub pool_idle_timeout: u64,
+    /// Units in second, 0 to disable TCP keepalive
+    pub tcp_keepalive: u64,
+    /// Open an extra connection before each send and verify call to time
+    /// its dns, connect and tls phases, not done through `proxy`
+    pub phase_timing: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 2,
+            timeout: 5,
+            proxy: Default::default(),
+            headers: Default::default(),
+            http2_prior_knowledge: false,
+            keep_alive: true,
+            pool_idle_timeout: 90,
+            tcp_keepalive: 0,
+            phase_timing: false,
+        }
+    }
+}
+
+/// Certificates used by the http client of a chain, besides the system roots.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM bundle of extra trusted CAs
+    pub ca_file: String,
+    /// PEM client certificate for mTLS
+    pub cert_file: String,
+    /// PEM PKCS#8 key of `cert_file`
+    pub key_file: String,
+    /// A DNS override: urls pointing at `resolve_host` connect to
+    /// `resolve_addr` instead of its resolved address. The SNI and the
+    /// verified name are still the host of the url
+    pub resolve_host: String,
+    /// e.g. "10.0.0.1:443", required with `resolve_host`
+    pub resolve_addr: String,
+}
+
+/// A read-only call whose response is checked instead of waiting for a tx.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryProbe {
+    pub name: String,
+    pub url: String,
+    /// Sent with POST if not empty, otherwise the query is a GET. Rendered as a template
+    pub body: String,
+    /// JSON pointer of the checked value, e.g. "/data/block_number"
+    pub pointer: String,
+    pub numeric: bool,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /// Units in second
     pub sender_interval: u64,
@@ -37,7 +468,599 @@ pub struct Config {
     pub storage_path: String,
     pub verify_api_url: String,
     pub metrics_port: u16,
+    /// IANA name, for readable times and calendar months
+    pub timezone: Tz,
+    pub clock: ClockConfig,
+    pub skew: SkewConfig,
     pub chain_sender_vec: Vec<ChainSender>,
+    /// Directory of TOML files of one `ChainSender` each, appended to
+    /// `chain_sender_vec` in the order of their names
+    pub chain_sender_dir: String,
+}
+
+/// How far the local clock may drift before buckets are no longer finalized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SkewConfig {
+    /// Units in second
+    pub max_skew: u64,
+    /// "host:port" of an SNTP server, buckets are always finalized if empty.
+    /// The `Date` header of gateway responses is only exported per chain
+    pub sntp_server: String,
+    /// Units in second
+    pub sntp_interval: u64,
+}
+
+impl Default for SkewConfig {
+    fn default() -> Self {
+        Self {
+            max_skew: 5,
+            sntp_server: Default::default(),
+            sntp_interval: 60,
+        }
+    }
+}
+
+/// Periodically fetch the latest block to check that blocks are still produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LivenessProbe {
+    pub url: String,
+    /// Sent with POST if not empty, otherwise the query is a GET. Rendered as a template
+    pub body: String,
+    /// JSON pointer of the latest block height
+    pub height_pointer: String,
+    /// JSON pointer of the latest block time, in seconds or ms. Optional
+    pub time_pointer: String,
+    /// Units in second, the height must advance within this window
+    pub stall_window: u64,
+    /// Units in second, between two fetches of the latest block
+    pub interval: u64,
+}
+
+impl Default for LivenessProbe {
+    fn default() -> Self {
+        Self {
+            url: Default::default(),
+            body: Default::default(),
+            height_pointer: Default::default(),
+            time_pointer: Default::default(),
+            stall_window: 60,
+            interval: 10,
+        }
+    }
+}
+
+/// Steps run in order, the scenario passes when every step does.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    /// Rendered once per run, so that later steps can check what former ones wrote
+    pub vars: BTreeMap<String, String>,
+    pub step_vec: Vec<ScenarioStep>,
+}
+
+/// `url`, `body` and the `expect` values are templates, besides the builtins
+/// they may refer to `chain_name`, the scenario `vars` and everything
+/// captured by former steps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScenarioStep {
+    pub name: String,
+    pub url: String,
+    /// Sent with POST if not empty, otherwise the step is a GET
+    pub body: String,
+    /// Variable name -> JSON pointer of the response
+    pub capture: BTreeMap<String, String>,
+    /// JSON pointer of the response -> expected value
+    pub expect: BTreeMap<String, String>,
+    /// Units in second, retry the step until it passes or this elapses
+    pub wait: u64,
+    /// Units in second
+    pub retry_interval: u64,
+}
+
+impl Scenario {
+    /// Units in second, the longest a run may take, every step may wait
+    /// `wait` and then one more `retry_interval` and call
+    pub fn max_duration(&self, http_timeout: u64) -> u64 {
+        self.step_vec
+            .iter()
+            .map(|step| step.wait + step.retry_interval + http_timeout)
+            .sum()
+    }
+}
+
+impl Default for ScenarioStep {
+    fn default() -> Self {
+        Self {
+            name: Default::default(),
+            url: Default::default(),
+            body: Default::default(),
+            capture: Default::default(),
+            expect: Default::default(),
+            wait: 0,
+            retry_interval: 3,
+        }
+    }
+}
+
+impl Config {
+    /// The timezone of `chain_sender`
+    pub fn timezone_of(&self, chain_sender: &ChainSender) -> Tz {
+        chain_sender.timezone.unwrap_or(self.timezone)
+    }
+
+    /// Everything the client can't run or report correctly without, listing
+    /// every problem found with the index of the chain it is of if any
+    fn problems(&self) -> Vec<(Option<usize>, String)> {
+        let mut errors = vec![];
+        for (name, value) in [
+            ("sender_interval", self.sender_interval),
+            ("validator_interval", self.validator_interval),
+            ("validator_timeout", self.validator_timeout),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} must be positive", name));
+            }
+        }
+        if self.validator_timeout <= self.validator_interval {
+            errors.push(format!(
+                "validator_timeout {}s must be longer than validator_interval {}s, or txs time out before they are verified",
+                self.validator_timeout, self.validator_interval
+            ));
+        }
+        check_url(&mut errors, "verify_api_url", &self.verify_api_url);
+        if let ClockConfig::Simulated { speed, .. } = self.clock {
+            if speed <= 0.0 {
+                errors.push(format!("clock.speed {} must be positive", speed));
+            }
+        }
+        if self.skew.sntp_interval == 0 {
+            errors.push("skew.sntp_interval must be positive".to_string());
+        }
+
+        if self.chain_sender_vec.is_empty() {
+            errors.push("chain_sender_vec has no chains to probe".to_string());
+        }
+        let mut problems: Vec<(Option<usize>, String)> =
+            errors.into_iter().map(|error| (None, error)).collect();
+
+        // Series names are both storage keys and, snake cased, metric names
+        let mut chain_names = HashSet::new();
+        let mut metric_names = HashMap::new();
+        for (i, chain_sender) in self.chain_sender_vec.iter().enumerate() {
+            let mut errors = vec![];
+            let at = format!("chain_sender_vec[{}] '{}'", i, chain_sender.chain_name);
+            if chain_sender.chain_name.is_empty() {
+                errors.push(format!("{}: chain_name must not be empty", at));
+            } else if !chain_names.insert(&chain_sender.chain_name) {
+                errors.push(format!("{}: chain_name is duplicated", at));
+            }
+            for name in chain_sender.series_names() {
+                let metric_name = name.to_snake_case();
+                if !is_metric_name(&metric_name) {
+                    errors.push(format!(
+                        "{}: series '{}' is not a valid metric name as '{}', use letters, digits, '-' and '_' and start with a letter",
+                        at, name, metric_name
+                    ));
+                } else if let Some(other) = metric_names.insert(metric_name.clone(), name.clone()) {
+                    errors.push(format!(
+                        "{}: series '{}' and '{}' have the same metric name '{}'",
+                        at, name, other, metric_name
+                    ));
+                }
+            }
+            chain_sender.validate(
+                &mut errors,
+                &at,
+                self.sender_interval,
+                self.validator_timeout,
+            );
+            problems.extend(errors.into_iter().map(|error| (Some(i), error)));
+        }
+        problems
+    }
+}
+
+impl ChainSender {
+    fn validate(
+        &self,
+        errors: &mut Vec<String>,
+        at: &str,
+        sender_interval: u64,
+        validator_timeout: u64,
+    ) {
+        check_url(errors, &format!("{}: sender_url", at), &self.sender_url);
+        if self.bucket_secs == 0 {
+            errors.push(format!("{}: bucket_secs must be positive", at));
+        } else if self.bucket_secs < sender_interval {
+            errors.push(format!(
+                "{}: bucket_secs {}s must not be shorter than sender_interval {}s, or some buckets have no data",
+                at, self.bucket_secs, sender_interval
+            ));
+        } else if self.bucket_secs > MAX_BUCKET_SECS {
+            errors.push(format!(
+                "{}: bucket_secs {}s must not be longer than {}s",
+                at, self.bucket_secs, MAX_BUCKET_SECS
+            ));
+        }
+        if self.http.timeout == 0 || self.http.timeout >= validator_timeout {
+            errors.push(format!(
+                "{}: http.timeout {}s must be positive and shorter than validator_timeout {}s",
+                at, self.http.timeout, validator_timeout
+            ));
+        }
+        match (
+            self.tls.resolve_host.is_empty(),
+            self.tls.resolve_addr.parse::<SocketAddr>(),
+        ) {
+            (true, _) if self.tls.resolve_addr.is_empty() => {}
+            (false, Ok(_)) => {}
+            (_, Err(e)) => errors.push(format!(
+                "{}: tls.resolve_addr '{}' is not a socket address for tls.resolve_host '{}': {}",
+                at, self.tls.resolve_addr, self.tls.resolve_host, e
+            )),
+            (true, Ok(_)) => errors.push(format!(
+                "{}: tls.resolve_addr '{}' is set without tls.resolve_host",
+                at, self.tls.resolve_addr
+            )),
+        }
+        if !self.http.proxy.is_empty() {
+            check_url(errors, &format!("{}: http.proxy", at), &self.http.proxy);
+        }
+        if self.retry.max_attempts == 0 {
+            errors.push(format!("{}: retry.max_attempts must be at least 1", at));
+        } else if sender_interval != 0 {
+            // The sends of a chain are serial and start again every sender_interval
+            let user_codes = self.user_codes().len() as u64;
+            let send_duration = self.retry.max_duration(self.http.timeout);
+            if user_codes * send_duration >= sender_interval * 1000 {
+                errors.push(format!(
+                    "{}: {} user code(s) sending for up to {}ms each with http.timeout and retry must take shorter than sender_interval {}s",
+                    at, user_codes, send_duration, sender_interval
+                ));
+            }
+            if self.bucket_secs / sender_interval * user_codes > u32::MAX as u64 {
+                errors.push(format!(
+                    "{}: {} user code(s) sending every {}s overflow the counters of a {}s bucket",
+                    at, user_codes, sender_interval, self.bucket_secs
+                ));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.availability.min_success_ratio) {
+            errors.push(format!(
+                "{}: availability.min_success_ratio {} must be within 0 and 1",
+                at, self.availability.min_success_ratio
+            ));
+        }
+
+        let mut payload_names = HashSet::new();
+        for payload in self.payloads() {
+            if payload.name.is_empty() || !payload_names.insert(payload.name.clone()) {
+                errors.push(format!(
+                    "{}: payload name '{}' must be non-empty and unique",
+                    at, payload.name
+                ));
+            }
+            let data = render(&payload.data, &self.template_vars());
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(&data) {
+                errors.push(format!(
+                    "{}: data of payload '{}' is not valid JSON after rendering: {}",
+                    at, payload.name, e
+                ));
+            }
+        }
+        if matches!(self.payload_selection, PayloadSelection::Weighted)
+            && self.payloads().iter().all(|payload| payload.weight == 0)
+        {
+            errors.push(format!("{}: payloads must not all weigh 0", at));
+        }
+
+        let user_codes = self.user_codes();
+        if user_codes.iter().collect::<HashSet<_>>().len() != user_codes.len() {
+            errors.push(format!("{}: user_code_vec is duplicated", at));
+        }
+        if let TenantRule::Quorum(quorum) = self.tenant_rule {
+            if quorum == 0 || quorum > user_codes.len() {
+                errors.push(format!(
+                    "{}: tenant_rule quorum {} must be within 1 and {} user codes",
+                    at,
+                    quorum,
+                    user_codes.len()
+                ));
+            }
+        }
+
+        for query in &self.query_probe_vec {
+            if query.name.is_empty() {
+                errors.push(format!("{}: query_probe_vec name must not be empty", at));
+            }
+            check_url(
+                errors,
+                &format!("{}: url of query '{}'", at, query.name),
+                &query.url,
+            );
+        }
+        if let Some(liveness) = &self.liveness {
+            check_url(errors, &format!("{}: liveness.url", at), &liveness.url);
+            if liveness.interval == 0 || liveness.interval > liveness.stall_window {
+                errors.push(format!(
+                    "{}: liveness.interval {}s must be positive and not longer than stall_window {}s",
+                    at, liveness.interval, liveness.stall_window
+                ));
+            }
+        }
+        for scenario in &self.scenario_vec {
+            if scenario.name.is_empty() {
+                errors.push(format!("{}: scenario_vec name must not be empty", at));
+            }
+            // The result is written into the bucket the run started in, which
+            // is finalized `validator_timeout` after it ends
+            let max_duration = scenario.max_duration(self.http.timeout);
+            if max_duration >= validator_timeout {
+                errors.push(format!(
+                    "{}: scenario '{}' may take {}s with the waits, retry intervals and http.timeout of its steps, it must be shorter than validator_timeout {}s",
+                    at, scenario.name, max_duration, validator_timeout
+                ));
+            }
+            for step in &scenario.step_vec {
+                if step.wait >= validator_timeout {
+                    errors.push(format!(
+                        "{}: wait {}s of step '{}/{}' must be shorter than validator_timeout {}s",
+                        at, step.wait, scenario.name, step.name, validator_timeout
+                    ));
+                }
+            }
+            // Urls referring to variables are only known when the scenario runs
+            for step in scenario
+                .step_vec
+                .iter()
+                .filter(|step| !step.url.contains("{{"))
+            {
+                check_url(
+                    errors,
+                    &format!("{}: url of step '{}/{}'", at, scenario.name, step.name),
+                    &step.url,
+                );
+            }
+        }
+        if let Some(load) = &self.load_test {
+            if load.target_tps <= 0.0 {
+                errors.push(format!(
+                    "{}: load_test.target_tps {} must be positive",
+                    at, load.target_tps
+                ));
+            }
+            if load.interval == 0 {
+                errors.push(format!("{}: load_test.interval must be positive", at));
+            }
+            if load.ramp_up + load.steady + load.ramp_down == 0 {
+                errors.push(format!(
+                    "{}: load_test.ramp_up, steady and ramp_down must not all be 0",
+                    at
+                ));
+            }
+            if load.max_in_flight == 0 {
+                errors.push(format!("{}: load_test.max_in_flight must be positive", at));
+            }
+        }
+    }
+}
+
+fn check_url(errors: &mut Vec<String>, name: &str, url: &str) {
+    match Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        Ok(url) => errors.push(format!(
+            "{} '{}' must be http or https, not '{}'",
+            name,
+            url,
+            url.scheme()
+        )),
+        Err(e) => errors.push(format!("{} '{}' is not a valid url: {}", name, url, e)),
+    }
+}
+
+/// Whether `name` matches `[a-zA-Z_][a-zA-Z0-9_]*` of prometheus
+fn is_metric_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The chain files of `chain_sender_dir`, each reloaded on its own when it changes
+#[derive(Debug, Default)]
+pub struct ChainDir {
+    /// When each file was modified and its chain, a broken file keeps the
+    /// chain loaded before
+    files: BTreeMap<PathBuf, (SystemTime, Option<ChainSender>)>,
+    /// The chain of each file in the config applied last, which is the one
+    /// accepted before if the loaded one is invalid
+    accepted: BTreeMap<PathBuf, ChainSender>,
+}
+
+impl ChainDir {
+    /// Reload the files of `dir` added, changed or removed since the last
+    /// call. Returns whether any chain changed and the files failed to load
+    pub fn reload(&mut self, dir: &str) -> (bool, Vec<String>) {
+        let mut paths = BTreeMap::new();
+        if !dir.is_empty() {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    return (
+                        false,
+                        vec![format!("read chain_sender_dir '{}' failed: {}", dir, e)],
+                    )
+                }
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                // Follows the symlinks of mounted ConfigMaps
+                let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified());
+                if let (true, Ok(modified)) =
+                    (path.extension().is_some_and(|ext| ext == "toml"), modified)
+                {
+                    paths.insert(path, modified);
+                }
+            }
+        }
+
+        let mut changed = false;
+        self.files.retain(|path, _| {
+            let kept = paths.contains_key(path);
+            changed |= !kept;
+            kept
+        });
+        let mut errors = vec![];
+        for (path, modified) in paths {
+            if self
+                .files
+                .get(&path)
+                .is_some_and(|(loaded, _)| *loaded == modified)
+            {
+                continue;
+            }
+            let chain_sender = match file_config::<ChainSender>(&path.to_string_lossy()) {
+                Ok(chain_sender) => {
+                    changed = true;
+                    Some(chain_sender)
+                }
+                Err(e) => {
+                    errors.push(format!(
+                        "load chain file '{}' failed: {}",
+                        path.display(),
+                        e
+                    ));
+                    self.files
+                        .get(&path)
+                        .and_then(|(_, chain_sender)| chain_sender.clone())
+                }
+            };
+            self.files.insert(path, (modified, chain_sender));
+        }
+        (changed, errors)
+    }
+
+    /// Every chain of the config applied last, with its file
+    pub fn chain_senders(&self) -> impl Iterator<Item = (&PathBuf, &ChainSender)> {
+        self.accepted.iter()
+    }
+}
+
+/// `file` with the chains of `chain_dir` and `overrides` applied. The chain of
+/// a file that is invalid on its own is replaced by the one accepted from the
+/// file before, or skipped if there is none or that is invalid too, which is
+/// returned as an error. Fails if anything else is invalid, `chain_dir` only
+/// takes the chains of the config once it is loaded
+pub fn load(
+    file: &Config,
+    chain_dir: &mut ChainDir,
+    overrides: &Overrides,
+) -> Result<(Config, Vec<String>)> {
+    let mut errors = vec![];
+    let mut rejected_before = HashSet::new();
+    // Invalid chains are replaced here, the loaded ones stay for the next load
+    let mut chains: BTreeMap<PathBuf, ChainSender> = chain_dir
+        .files
+        .iter()
+        .filter_map(|(path, (_, chain_sender))| Some((path.clone(), chain_sender.clone()?)))
+        .collect();
+    loop {
+        let mut merged = file.clone();
+        merged.chain_sender_vec.extend(chains.values().cloned());
+        let config = overrides.apply(&merged)?;
+        let mut rejected: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+        let mut invalid = vec![];
+        for (i, problem) in config.problems() {
+            // The chains of `chain_dir` follow those of `file`
+            let path = i
+                .and_then(|i| i.checked_sub(file.chain_sender_vec.len()))
+                .and_then(|i| chains.keys().nth(i))
+                .cloned();
+            match path {
+                Some(path) => rejected.entry(path).or_default().push(problem),
+                None => invalid.push(problem),
+            }
+        }
+        if rejected.is_empty() {
+            if !invalid.is_empty() {
+                return Err(eyre!("invalid config:\n  - {}", invalid.join("\n  - ")));
+            }
+            chain_dir.accepted = chains;
+            return Ok((config, errors));
+        }
+        for (path, problems) in rejected {
+            // The chain accepted before is tried once, then the file is skipped
+            let accepted = chain_dir
+                .accepted
+                .get(&path)
+                .filter(|_| rejected_before.insert(path.clone()))
+                .cloned();
+            let kept = accepted.is_some();
+            match accepted {
+                Some(chain_sender) => chains.insert(path.clone(), chain_sender),
+                None => chains.remove(&path),
+            };
+            errors.push(format!(
+                "invalid chain file '{}', {}:\n  - {}",
+                path.display(),
+                if kept {
+                    "keep the chain accepted before"
+                } else {
+                    "skip it"
+                },
+                problems.join("\n  - ")
+            ));
+        }
+    }
+}
+
+/// Hot reload `config_path` and the files of `chain_dir` into `config` with
+/// `overrides` applied, `file` is the config loaded from `config_path`. A
+/// reloaded config is only applied once it is valid, otherwise the previous
+/// one is kept
+pub fn hot_reload(
+    config: Arc<RwLock<Config>>,
+    file: Config,
+    config_path: String,
+    overrides: Overrides,
+    mut chain_dir: ChainDir,
+) -> Result<()> {
+    let candidate = Arc::new(RwLock::new(file));
+    config_hot_reload(candidate.clone(), config_path)?;
+    tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_secs(1));
+        let mut seen = serde_json::to_value(&*candidate.read()).ok();
+        loop {
+            ticker.tick().await;
+            let reloaded = candidate.read().clone();
+            let (chain_dir_changed, errors) = chain_dir.reload(
+                overrides
+                    .get("chain_sender_dir")
+                    .unwrap_or(&reloaded.chain_sender_dir),
+            );
+            for e in errors {
+                error!("{}, keep the chain accepted before", e);
+            }
+            let reloaded_value = serde_json::to_value(&reloaded).ok();
+            if reloaded_value == seen && !chain_dir_changed {
+                continue;
+            }
+            seen = reloaded_value;
+            match load(&reloaded, &mut chain_dir, &overrides) {
+                Ok((reloaded, errors)) => {
+                    for e in errors {
+                        error!("{}", e);
+                    }
+                    info!("reloaded config applied");
+                    *config.write() = reloaded;
+                }
+                Err(e) => error!("reloaded config rejected, keep the previous one: {}", e),
+            }
+        }
+    });
+    Ok(())
 }
 
 impl Default for Config {
@@ -49,8 +1072,163 @@ impl Default for Config {
             storage_path: "default_db".to_string(),
             verify_api_url: "http://127.0.0.1:3000/auto_tx/api/get_onchain_hash".to_string(),
             metrics_port: 61616,
+            timezone: Tz::Asia__Shanghai,
+            clock: Default::default(),
+            skew: Default::default(),
             chain_sender_vec: vec![],
+            chain_sender_dir: Default::default(),
             validator_timeout: 300,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::chain_sender;
+
+    fn config(chain_sender_vec: Vec<ChainSender>) -> Config {
+        Config {
+            chain_sender_vec,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        assert_eq!(config(vec![chain_sender("a")]).problems(), vec![]);
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_chain() {
+        let step = |wait| ScenarioStep {
+            name: "wait".to_string(),
+            url: "http://gateway/status".to_string(),
+            wait,
+            ..Default::default()
+        };
+        let cases: Vec<(Config, &[&str])> = vec![
+            (
+                config(vec![chain_sender("a"), chain_sender("a")]),
+                &[
+                    "chain_sender_vec[1] 'a': chain_name is duplicated",
+                    "chain_sender_vec[1] 'a': series 'a' and 'a' have the same metric name 'a'",
+                ],
+            ),
+            (
+                config(vec![chain_sender("a-b"), chain_sender("a_b")]),
+                &["chain_sender_vec[1] 'a_b': series 'a_b' and 'a-b' have the same metric name 'a_b'"],
+            ),
+            (
+                config(vec![ChainSender {
+                    data_for_send: r#"{"chain": {{chain_name}}}"#.to_string(),
+                    ..chain_sender("a")
+                }]),
+                &["chain_sender_vec[0] 'a': data of payload 'default' is not valid JSON after rendering: expected value at line 1 column 11"],
+            ),
+            (
+                config(vec![ChainSender {
+                    bucket_secs: 10,
+                    ..chain_sender("a")
+                }]),
+                &["chain_sender_vec[0] 'a': bucket_secs 10s must not be shorter than sender_interval 30s, or some buckets have no data"],
+            ),
+            (
+                config(vec![ChainSender {
+                    retry: RetryPolicy {
+                        max_attempts: 6,
+                        backoff: 1000,
+                        ..Default::default()
+                    },
+                    ..chain_sender("a")
+                }]),
+                &["chain_sender_vec[0] 'a': 1 user code(s) sending for up to 61000ms each with http.timeout and retry must take shorter than sender_interval 30s"],
+            ),
+            (
+                config(vec![ChainSender {
+                    scenario_vec: vec![Scenario {
+                        name: "slow".to_string(),
+                        step_vec: vec![step(100), step(100), step(100)],
+                        ..Default::default()
+                    }],
+                    ..chain_sender("a")
+                }]),
+                &["chain_sender_vec[0] 'a': scenario 'slow' may take 324s with the waits, retry intervals and http.timeout of its steps, it must be shorter than validator_timeout 300s"],
+            ),
+            (
+                config(vec![ChainSender {
+                    load_test: Some(LoadTest {
+                        interval: 0,
+                        ..Default::default()
+                    }),
+                    ..chain_sender("a")
+                }]),
+                &["chain_sender_vec[0] 'a': load_test.interval must be positive"],
+            ),
+            (
+                config(vec![ChainSender {
+                    load_test: Some(LoadTest {
+                        ramp_up: 0,
+                        steady: 0,
+                        ramp_down: 0,
+                        ..Default::default()
+                    }),
+                    ..chain_sender("a")
+                }]),
+                &["chain_sender_vec[0] 'a': load_test.ramp_up, steady and ramp_down must not all be 0"],
+            ),
+        ];
+        for (config, expected) in cases {
+            let problems: Vec<String> = config
+                .problems()
+                .into_iter()
+                .map(|(_, problem)| problem)
+                .collect();
+            assert_eq!(problems, expected);
+        }
+    }
+
+    #[test]
+    fn dir_chain_skipped_by_a_global_change_returns_after_the_revert() {
+        let dir = std::env::temp_dir().join(format!("sla-client-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("b.toml");
+        std::fs::write(
+            &path,
+            "chain_name = \"b\"\nsender_url = \"http://gateway/send_tx\"\ndata_for_send = \"{}\"\n\n[http]\ntimeout = 20\n",
+        )
+        .unwrap();
+        let dir = dir.to_string_lossy().to_string();
+        let mut chain_dir = ChainDir::default();
+        assert_eq!(chain_dir.reload(&dir), (true, vec![]));
+        let file = config(vec![chain_sender("a")]);
+        let overrides = Overrides::default();
+        let (config, errors) = load(&file, &mut chain_dir, &overrides).unwrap();
+        assert_eq!((config.chain_sender_vec.len(), errors.len()), (2, 0));
+
+        // Shorter than http.timeout of the chain
+        let lowered = Config {
+            validator_timeout: 15,
+            ..file.clone()
+        };
+        let (config, errors) = load(&lowered, &mut chain_dir, &overrides).unwrap();
+        assert_eq!(config.chain_sender_vec.len(), 1);
+        assert!(
+            errors[1].starts_with(&format!("invalid chain file '{}', skip it", path.display())),
+            "{:?}",
+            errors
+        );
+        // Failing to load after skipping the chain leaves the chains as they are
+        let invalid = Config {
+            sender_interval: 0,
+            ..lowered
+        };
+        assert!(load(&invalid, &mut chain_dir, &overrides).is_err());
+
+        assert_eq!(chain_dir.reload(&dir), (false, vec![]));
+        let (config, errors) = load(&file, &mut chain_dir, &overrides).unwrap();
+        assert_eq!((config.chain_sender_vec.len(), errors.len()), (2, 0));
+        assert_eq!(config.chain_sender_vec[1].chain_name, "b");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    clock::Clock, config::Config, record::VerifiedResult, skew::exceeded,
+    time::get_latest_finalized_bucket,
+};
+use flume::Sender;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+use storage_dal::{Storage, StorageData};
+use tokio::time::interval;
+
+/// The latest bucket of a series counted by metrics, recovery counts the
+/// buckets up to it and the finalizer emits the later ones
+#[derive(StorageData, Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Watermark {
+    pub name: String,
+    /// Units in buckets of `bucket_secs`
+    pub bucket: u64,
+    /// Units in second
+    pub bucket_secs: u64,
+}
+
+impl Watermark {
+    /// Units in second, when the bucket ends
+    pub const fn end_secs(&self) -> u64 {
+        (self.bucket + 1) * self.bucket_secs
+    }
+}
+
+/// Emit every bucket of every series to metrics once it is older than
+/// `validator_timeout`, on the schedule of `validator_interval`.
+pub async fn run(
+    storage: Storage,
+    config: Arc<RwLock<Config>>,
+    vr_sender: Sender<VerifiedResult>,
+    clock: Arc<dyn Clock>,
+) {
+    let mut ticker = interval(Duration::from_secs(config.read().validator_interval));
+    // Units in second, when the latest bucket emitted of each series ends,
+    // metrics may not have counted it yet
+    let mut emitted = HashMap::new();
+    loop {
+        ticker.tick().await;
+        let config = config.read().clone();
+        finalize(&storage, &config, &vr_sender, clock.now(), &mut emitted);
+    }
+}
+
+pub(crate) fn finalize(
+    storage: &Storage,
+    config: &Config,
+    vr_sender: &Sender<VerifiedResult>,
+    now: u64,
+    emitted: &mut HashMap<String, u64>,
+) {
+    if let Some(skew) = exceeded(&config.skew) {
+        warn!(
+            "clock skew to the sntp server is {}s, beyond {}s, buckets are not finalized",
+            skew, config.skew.max_skew
+        );
+        return;
+    }
+    let configured: BTreeMap<String, u64> = config
+        .chain_sender_vec
+        .iter()
+        .flat_map(|chain_sender| {
+            chain_sender
+                .series_names()
+                .into_iter()
+                .map(|name| (name, chain_sender.bucket_secs))
+        })
+        .collect();
+    // Series removed from the config still have their last buckets finalized
+    let watermarks: Vec<Watermark> = storage
+        .scan::<Watermark>()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| storage.get_by_path::<Watermark>(entry.path()))
+        .collect();
+    let names: BTreeSet<String> = watermarks
+        .iter()
+        .map(|watermark| watermark.name.clone())
+        .chain(configured.keys().cloned())
+        .collect();
+
+    for name in names {
+        let watermark = watermarks.iter().find(|watermark| watermark.name == name);
+        let bucket_secs = configured
+            .get(&name)
+            .copied()
+            .or(watermark.map(|watermark| watermark.bucket_secs))
+            .unwrap_or(60);
+        let finalized_bucket =
+            get_latest_finalized_bucket(now, config.validator_timeout, bucket_secs);
+        let Some(watermark) = watermark else {
+            // Created by the recovery of metrics, the buckets are finalized after that
+            continue;
+        };
+        // The first bucket starting after the emitted ones, the size may have changed since
+        let emitted_end = emitted
+            .get(&name)
+            .map_or(watermark.end_secs(), |&end| end.max(watermark.end_secs()));
+        let from = emitted_end.div_ceil(bucket_secs);
+        for bucket in from..=finalized_bucket {
+            let key = format!("{}/{}", name, bucket);
+            let vr = match storage.get::<VerifiedResult>(&key) {
+                Some(vr) => vr,
+                None if configured.contains_key(&name) => {
+                    let vr = VerifiedResult {
+                        no_data: true,
+                        ..VerifiedResult::new(bucket, bucket_secs, name.clone())
+                    };
+                    storage.insert(&key, vr.clone());
+                    vr
+                }
+                None => continue,
+            };
+            debug!("finalize: {:?}", &vr);
+            let _ = vr_sender.send(vr);
+        }
+        if from <= finalized_bucket {
+            emitted.insert(name, (finalized_bucket + 1) * bucket_secs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::tests::{chain_sender, storage},
+        metrics::Counting,
+    };
+
+    const BUCKET_MS: u64 = 60_000;
+    /// Units in ms, the watermark is put here by the recovery of metrics
+    const START: u64 = 1_700_000_040_000;
+
+    /// Where a run of the client is restarted while counting the emitted buckets
+    #[derive(Debug, Clone, Copy)]
+    enum Crash {
+        /// After writing the buckets, before counting the `n`th one
+        BeforeCount(usize),
+        /// After counting the `n`th one, before moving the watermark to it
+        BeforeWatermark(usize),
+        /// After counting the `n`th one and moving the watermark to it
+        AfterWatermark(usize),
+    }
+
+    /// The observed, unavailable and no data buckets of a series of six buckets,
+    /// two of them without data, counted through a restart at `crash`
+    fn counted(crash: Option<Crash>) -> (u64, u64, u64) {
+        let chain_sender = chain_sender("restart");
+        let config = Config {
+            chain_sender_vec: vec![chain_sender],
+            ..Default::default()
+        };
+        let storage = storage();
+        let (vr_sender, vr_receiver) = flume::unbounded();
+        let mut emitted = HashMap::new();
+        let mut counting = Counting::new(storage.clone(), false);
+        counting.add_configured(&config, START);
+        let first = storage.get::<Watermark>("restart").unwrap().bucket + 1;
+        for (bucket, sent_failed, succeed) in [(0, 0, 1), (1, 0, 0), (3, 1, 0), (4, 0, 1)] {
+            storage.insert(
+                &format!("restart/{}", first + bucket),
+                VerifiedResult {
+                    sent_num: 1 - sent_failed,
+                    sent_failed_num: sent_failed,
+                    succeed_num: succeed,
+                    failed_num: 1 - sent_failed - succeed,
+                    ..VerifiedResult::new(first + bucket, 60, "restart".to_string())
+                },
+            );
+        }
+
+        let now = START + 6 * BUCKET_MS;
+        finalize(&storage, &config, &vr_sender, now, &mut emitted);
+        for (n, vr) in vr_receiver.drain().enumerate() {
+            let watermark = storage.get::<Watermark>("restart");
+            match crash {
+                Some(Crash::BeforeCount(at)) if at == n => {}
+                Some(Crash::BeforeWatermark(at)) if at == n => {
+                    counting.count(&config, &vr, now);
+                    storage.insert("restart", watermark.unwrap());
+                }
+                Some(Crash::AfterWatermark(at)) if at == n => counting.count(&config, &vr, now),
+                _ => {
+                    counting.count(&config, &vr, now);
+                    continue;
+                }
+            }
+            // Restarted with nothing but the storage
+            counting = Counting::new(storage.clone(), false);
+            counting.add_configured(&config, now);
+            emitted = HashMap::new();
+            finalize(&storage, &config, &vr_sender, now, &mut emitted);
+            for vr in vr_receiver.drain() {
+                counting.count(&config, &vr, now);
+            }
+            break;
+        }
+        counting.counted("restart")
+    }
+
+    #[test]
+    fn every_bucket_is_counted_once() {
+        let (_, unavailable, no_data) = counted(None);
+        assert_eq!((unavailable, no_data), (2, 2));
+    }
+
+    #[test]
+    fn restart_at_any_boundary_counts_every_bucket_once() {
+        let expected = counted(None);
+        for n in 0..6 {
+            for crash in [
+                Crash::BeforeCount(n),
+                Crash::BeforeWatermark(n),
+                Crash::AfterWatermark(n),
+            ] {
+                assert_eq!(counted(Some(crash)), expected, "{:?}", crash);
+            }
+        }
+    }
+}
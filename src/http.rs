@@ -0,0 +1,164 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    clock,
+    config::{ChainSender, HttpConfig, TlsConfig},
+    metrics::CERT_EXPIRY_DAYS,
+};
+use color_eyre::eyre::{eyre, Result};
+use parking_lot::Mutex;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    tls::TlsInfo,
+    Certificate, Identity, Proxy, RequestBuilder, Response,
+};
+use std::{collections::HashMap, fs, time::Duration};
+
+/// Http clients of every chain, rebuilt when the settings of the chain change.
+#[derive(Debug, Default)]
+pub struct HttpClients {
+    clients: Mutex<HashMap<String, ChainHttpClient>>,
+}
+
+#[derive(Debug)]
+struct ChainHttpClient {
+    http: HttpConfig,
+    tls: TlsConfig,
+    http_client: reqwest::Client,
+}
+
+impl HttpClients {
+    pub fn get(&self, chain_sender: &ChainSender) -> Result<reqwest::Client> {
+        let mut clients = self.clients.lock();
+        if let Some(client) = clients.get(&chain_sender.chain_name) {
+            if client.http == chain_sender.http && client.tls == chain_sender.tls {
+                return Ok(client.http_client.clone());
+            }
+        }
+        let http_client = build(&chain_sender.http, &chain_sender.tls).map_err(|e| {
+            eyre!(
+                "build http client for '{}' failed: {}",
+                chain_sender.chain_name,
+                e
+            )
+        })?;
+        info!("build http client for '{}'", chain_sender.chain_name);
+        clients.insert(
+            chain_sender.chain_name.clone(),
+            ChainHttpClient {
+                http: chain_sender.http.clone(),
+                tls: chain_sender.tls.clone(),
+                http_client: http_client.clone(),
+            },
+        );
+        Ok(http_client)
+    }
+
+    /// A request to `url` with the client of `chain_sender` and `headers`, a JSON POST
+    /// of `body` or a GET when it is empty, authorized by the chain
+    pub async fn request(
+        &self,
+        chain_sender: &ChainSender,
+        url: &str,
+        body: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<RequestBuilder> {
+        let http_client = self.get(chain_sender)?;
+        let mut request = if body.is_empty() {
+            http_client.get(url)
+        } else {
+            http_client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+        };
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        chain_sender
+            .auth
+            .apply(&http_client, request, body)
+            .await
+            .map_err(|e| eyre!("auth for '{}' failed: {}", url, e))
+    }
+}
+
+fn build(http: &HttpConfig, tls: &TlsConfig) -> Result<reqwest::Client> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &http.headers {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    let mut builder = reqwest::ClientBuilder::default()
+        .connect_timeout(Duration::from_secs(http.connect_timeout))
+        .timeout(Duration::from_secs(http.timeout))
+        .default_headers(headers)
+        .pool_idle_timeout(Duration::from_secs(http.pool_idle_timeout))
+        .tls_info(true);
+    if !http.proxy.is_empty() {
+        builder = builder.proxy(Proxy::all(&http.proxy)?);
+    }
+    if http.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    if !http.keep_alive {
+        builder = builder.pool_max_idle_per_host(0);
+    }
+    if http.tcp_keepalive != 0 {
+        builder = builder.tcp_keepalive(Duration::from_secs(http.tcp_keepalive));
+    }
+    if !tls.ca_file.is_empty() {
+        for cert in Certificate::from_pem_bundle(&fs::read(&tls.ca_file)?)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if !tls.cert_file.is_empty() {
+        builder = builder.identity(Identity::from_pkcs8_pem(
+            &fs::read(&tls.cert_file)?,
+            &fs::read(&tls.key_file)?,
+        )?);
+    }
+    if !tls.resolve_host.is_empty() {
+        builder = builder.resolve(&tls.resolve_host, tls.resolve_addr.parse()?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Export the days until the certificate of the server behind `resp` expires.
+pub fn observe_cert_expiry(chain_name: &str, resp: &Response) {
+    let Some(cert) = resp
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(|tls_info| tls_info.peer_certificate())
+    else {
+        return;
+    };
+    let endpoint = format!(
+        "{}:{}",
+        resp.url().host_str().unwrap_or_default(),
+        resp.url().port_or_known_default().unwrap_or_default()
+    );
+    match x509_parser::parse_x509_certificate(cert) {
+        Ok((_, cert)) => {
+            let expire_in = cert.validity().not_after.timestamp() * 1000 - clock::now() as i64;
+            CERT_EXPIRY_DAYS
+                .with_label_values(&[chain_name, &endpoint])
+                .set(expire_in as f64 / 86_400_000.0);
+        }
+        Err(e) => warn!("parse certificate of '{}' failed: {}", endpoint, e),
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    client::Client,
+    config::{ChainSender, LoadTest},
+    probe::{send_tx, wait_verified, Probe},
+};
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{path::Path, sync::Arc};
+use storage_dal::StorageData;
+use tokio::{
+    sync::Semaphore,
+    task::JoinSet,
+    time::{interval, Duration, Instant},
+};
+
+/// Latency percentiles, units in ms
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Percentiles {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Percentiles {
+    fn new(mut latencies: Vec<u64>) -> Self {
+        latencies.sort_unstable();
+        let at = |p: f64| {
+            latencies
+                .get(((latencies.len().max(1) - 1) as f64 * p).round() as usize)
+                .copied()
+                .unwrap_or_default()
+        };
+        Self {
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            max: at(1.0),
+        }
+    }
+}
+
+/// When the latest load test of a chain started, kept over restarts
+#[derive(StorageData, Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LoadTestStart {
+    pub chain_name: String,
+    /// Units in ms
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LoadSummary {
+    pub chain_name: String,
+    /// Units in ms
+    pub start: u64,
+    /// Units in ms
+    pub end: u64,
+    pub target_tps: f64,
+    pub sent: u64,
+    pub sent_failed: u64,
+    pub included: u64,
+    /// Included / sent
+    pub inclusion_rate: f64,
+    pub send_latency: Percentiles,
+    /// From sending to the first successful verify
+    pub inclusion_latency: Percentiles,
+}
+
+/// The send rate `elapsed` into a run of `load`
+fn target_rate(load: &LoadTest, elapsed: f64) -> f64 {
+    let ramp_up = load.ramp_up as f64;
+    let steady = load.steady as f64;
+    let ramp_down = load.ramp_down as f64;
+    if elapsed < ramp_up {
+        load.target_tps * elapsed / ramp_up
+    } else if elapsed < ramp_up + steady {
+        load.target_tps
+    } else if elapsed < ramp_up + steady + ramp_down {
+        load.target_tps * (ramp_up + steady + ramp_down - elapsed) / ramp_down
+    } else {
+        0.0
+    }
+}
+
+/// Send to `chain_sender` following `load`, verifying every sent tx right away.
+///
+/// Txs of a load test are neither stored nor counted in the SLA, their calls
+/// are exported as "load_send" and "load_verify".
+pub async fn run<P: Probe + Send + Sync + 'static>(
+    client: Arc<Client<P>>,
+    chain_sender: ChainSender,
+    load: LoadTest,
+    verify_api_url: String,
+    validator_timeout: u64,
+) -> LoadSummary {
+    let chain_sender = Arc::new(chain_sender);
+    let verify_api_url = Arc::new(verify_api_url);
+    let mut summary = LoadSummary {
+        chain_name: chain_sender.chain_name.clone(),
+        start: client.clock.now(),
+        target_tps: load.target_tps,
+        ..Default::default()
+    };
+    info!("load test of '{}' start: {:?}", summary.chain_name, load);
+
+    let duration = (load.ramp_up + load.steady + load.ramp_down) as f64;
+    let start = Instant::now();
+    let mut ticker = interval(Duration::from_millis(10));
+    let mut last_tick = 0.0;
+    let mut credit = 0.0;
+    let in_flight = Arc::new(Semaphore::new(load.max_in_flight));
+    let mut probes = JoinSet::new();
+    // Spread the load over all the tenants
+    let user_codes = chain_sender.user_codes();
+    let mut turn = 0;
+    loop {
+        ticker.tick().await;
+        let elapsed = start.elapsed().as_secs_f64();
+        if elapsed >= duration {
+            break;
+        }
+        credit += target_rate(&load, elapsed) * (elapsed - last_tick);
+        last_tick = elapsed;
+        while credit >= 1.0 {
+            credit -= 1.0;
+            // Sends wait here while `max_in_flight` txs are not verified yet
+            let Ok(permit) = in_flight.clone().acquire_owned().await else {
+                break;
+            };
+            let client = client.clone();
+            let chain_sender = chain_sender.clone();
+            let verify_api_url = verify_api_url.clone();
+            let user_code = user_codes[turn % user_codes.len()].clone();
+            // Not picked with `Client::next_payload`, the turns of the SLA probe stay as they are
+            let payload = chain_sender.pick_payload(turn as u64);
+            turn += 1;
+            probes.spawn(async move {
+                let _permit = permit;
+                let (record, utx) = send_tx(
+                    &client.probe,
+                    client.clock.as_ref(),
+                    &chain_sender,
+                    user_code,
+                    payload,
+                    "load_send",
+                )
+                .await;
+                let send_latency = client.clock.now() - record.timestamp;
+                let Some(utx) = utx else {
+                    return (send_latency, false, None);
+                };
+                let inclusion_latency = wait_verified(
+                    &client.probe,
+                    client.clock.as_ref(),
+                    &chain_sender,
+                    &utx,
+                    &verify_api_url,
+                    validator_timeout,
+                    "load_verify",
+                )
+                .await;
+                (send_latency, true, inclusion_latency)
+            });
+        }
+    }
+
+    let mut send_latencies = vec![];
+    let mut inclusion_latencies = vec![];
+    while let Some(probed) = probes.join_next().await {
+        let Ok((send_latency, sent, inclusion_latency)) = probed else {
+            continue;
+        };
+        summary.sent += 1;
+        send_latencies.push(send_latency);
+        if !sent {
+            summary.sent_failed += 1;
+        }
+        inclusion_latencies.extend(inclusion_latency);
+    }
+    summary.included = inclusion_latencies.len() as u64;
+    if summary.sent != 0 {
+        summary.inclusion_rate = summary.included as f64 / summary.sent as f64;
+    }
+    summary.send_latency = Percentiles::new(send_latencies);
+    summary.inclusion_latency = Percentiles::new(inclusion_latencies);
+    summary.end = client.clock.now();
+    info!("load test of '{}' end: {:?}", summary.chain_name, summary);
+
+    if !load.summary_dir.is_empty() {
+        if let Err(e) = write_summary(&load.summary_dir, &summary) {
+            error!("write load test summary failed: {}", e);
+        }
+    }
+    summary
+}
+
+fn write_summary(summary_dir: &str, summary: &LoadSummary) -> Result<()> {
+    std::fs::create_dir_all(summary_dir)?;
+    let path =
+        Path::new(summary_dir).join(format!("{}-{}.json", summary.chain_name, summary.start));
+    std::fs::write(&path, serde_json::to_string_pretty(summary)?)?;
+    info!("load test summary written to '{}'", path.display());
+    Ok(())
+}
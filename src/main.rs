@@ -12,34 +12,116 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod auth;
 mod client;
+mod clock;
 mod config;
+mod finalizer;
+mod http;
+mod load;
 mod metrics;
+mod overrides;
+mod phase;
+mod probe;
 mod record;
+mod report;
+mod scenario;
+mod skew;
+mod template;
 mod time;
 
 #[macro_use]
 extern crate tracing as logger;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use cloud_util::graceful_shutdown::graceful_shutdown;
 use color_eyre::eyre::Result;
-use common_rs::configure::{config_hot_reload, file_config};
+use common_rs::configure::file_config;
 use parking_lot::RwLock;
-use std::{sync::Arc, time::Duration};
+use std::{fmt, sync::Arc};
 use storage_dal::Storage;
 
 use client::Client;
-use config::Config;
+use clock::Clock;
+use config::{ChainDir, Config};
+use http::HttpClients;
 use metrics::run_metrics_exporter;
+use overrides::Overrides;
+use probe::AutoTxProbe;
 use record::VerifiedResult;
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
     /// config file path
-    #[arg(short, long, default_value = "config/client.toml")]
+    #[arg(short, long, default_value = "config/client.toml", global = true)]
     config: String,
+    /// override a config value, e.g. "chain_sender_vec.cita-test.sender_url=http://..."
+    #[arg(long, value_name = "PATH=VALUE", global = true)]
+    set: Vec<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl fmt::Debug for Args {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The value of a --set may be a secret
+        let set: Vec<String> = self
+            .set
+            .iter()
+            .map(|set| match set.split_once('=') {
+                Some((path, _)) => format!("{}=***", path),
+                None => set.clone(),
+            })
+            .collect();
+        f.debug_struct("Args")
+            .field("config", &self.config)
+            .field("set", &set)
+            .field("command", &self.command)
+            .finish()
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// run the client, the default
+    Run,
+    /// send one probe with every user code and wait for it to be verified,
+    /// exits non-zero if any fails
+    ProbeOnce {
+        /// only probe these chains
+        #[arg(long)]
+        chain: Vec<String>,
+    },
+    /// print the effective config and where each value is set, fails if a chain file is not taken as is
+    CheckConfig,
+    /// print the availability of a calendar month from storage,
+    /// which a running client holds locked: stop it first or
+    /// `--set storage_path=<a copy>`
+    Report {
+        /// YYYY-MM, the current month if not set
+        #[arg(long)]
+        month: Option<String>,
+        /// only report these chains
+        #[arg(long)]
+        chain: Vec<String>,
+    },
+    /// print the stored buckets between two days, from storage locked by
+    /// a running client as for `report`
+    Export {
+        /// YYYY-MM-DD, the first day
+        #[arg(long)]
+        from: String,
+        /// YYYY-MM-DD, the last day
+        #[arg(long)]
+        to: String,
+        /// only export these chains
+        #[arg(long)]
+        chain: Vec<String>,
+        /// print csv instead of json lines
+        #[arg(long)]
+        csv: bool,
+    },
 }
 
 fn main() {
@@ -48,7 +130,36 @@ fn main() {
     let rt = tokio::runtime::Runtime::new().unwrap();
 
     let args = Args::parse();
-    let config: Config = file_config(&args.config).unwrap_or_default();
+    let overrides = match Overrides::new(&args.set) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let file: Config = match file_config(&args.config) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("load config '{}' failed: {}", &args.config, e);
+            std::process::exit(1);
+        }
+    };
+    let mut chain_dir = ChainDir::default();
+    let (_, mut chain_errors) = chain_dir.reload(
+        overrides
+            .get("chain_sender_dir")
+            .unwrap_or(&file.chain_sender_dir),
+    );
+    let config = match config::load(&file, &mut chain_dir, &overrides) {
+        Ok((config, errors)) => {
+            chain_errors.extend(errors);
+            config
+        }
+        Err(e) => {
+            eprintln!("config '{}': {}", &args.config, e);
+            std::process::exit(1);
+        }
+    };
 
     // init tracer
     cloud_util::tracer::init_tracer("sla-client".to_owned(), &config.log_config)
@@ -56,34 +167,94 @@ fn main() {
         .unwrap();
 
     info!("{:?}", &args);
+    // A broken chain file only drops its chain
+    for e in &chain_errors {
+        error!("{}", e);
+    }
     info!("{:?}", &config);
-    if let Err(err) = rt.block_on(start(config, args.config)) {
-        error!("sla-client start err: {:?}", err);
+    let clock = config.clock.build();
+    clock::install(clock.clone());
+    let result = match args.command.clone().unwrap_or(Command::Run) {
+        Command::Run => rt.block_on(start(
+            config,
+            file,
+            args.config,
+            overrides,
+            chain_dir,
+            clock,
+        )),
+        Command::ProbeOnce { chain } => {
+            if !rt.block_on(probe::probe_once(&config, clock.as_ref(), &chain)) {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::CheckConfig => file_config(&args.config)
+            .and_then(|file| {
+                let chain_files = chain_dir
+                    .chain_senders()
+                    .map(|(path, _)| {
+                        let path = path.to_string_lossy();
+                        Ok((path.to_string(), file_config::<serde_json::Value>(&path)?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                overrides.describe(&config, &file, &chain_files)
+            })
+            .map(|lines| {
+                for line in lines {
+                    println!("{}", line);
+                }
+                // A chain file failed to load or is kept at the chain before or skipped
+                if !chain_errors.is_empty() {
+                    for e in &chain_errors {
+                        eprintln!("{}", e);
+                    }
+                    std::process::exit(1);
+                }
+            }),
+        Command::Report { month, chain } => report::report(
+            &Storage::init_sled(&config.storage_path),
+            &config,
+            month.as_deref(),
+            &chain,
+            clock.now(),
+        ),
+        Command::Export {
+            from,
+            to,
+            chain,
+            csv,
+        } => report::export(
+            &Storage::init_sled(&config.storage_path),
+            &config,
+            &from,
+            &to,
+            &chain,
+            csv,
+        ),
+    };
+    if let Err(err) = result {
+        error!("sla-client err: {:?}", err);
+        std::process::exit(1);
     }
 }
 
-async fn start(config: Config, config_path: String) -> Result<()> {
+async fn start(
+    config: Config,
+    file: Config,
+    config_path: String,
+    overrides: Overrides,
+    chain_dir: ChainDir,
+    clock: Arc<dyn Clock>,
+) -> Result<()> {
     let graceful_shutdown_rx = graceful_shutdown();
 
     let storage = Storage::init_sled(&config.storage_path);
-    let http_client = reqwest::ClientBuilder::default()
-        .connect_timeout(Duration::from_secs(2))
-        .timeout(Duration::from_secs(5))
-        .build()?;
+    let http_clients = Arc::new(HttpClients::default());
 
     let (vr_sender, vr_receiver) = flume::unbounded::<VerifiedResult>();
 
     let metrics_port = config.metrics_port;
-    tokio::spawn(crate::metrics::start(
-        vr_receiver,
-        storage.clone(),
-        config.validator_timeout,
-        config
-            .chain_sender_vec
-            .iter()
-            .map(|chain_sender| chain_sender.chain_name.clone())
-            .collect::<Vec<_>>(),
-    ));
     let graceful_shutdown_metrics = graceful_shutdown_rx.clone();
     tokio::spawn(run_metrics_exporter(
         metrics_port,
@@ -97,19 +268,42 @@ async fn start(config: Config, config_path: String) -> Result<()> {
 
     let config = Arc::new(RwLock::new(config));
 
-    config_hot_reload(config.clone(), config_path)?;
+    config::hot_reload(config.clone(), file, config_path, overrides, chain_dir)?;
 
-    let client = Client {
-        config,
-        storage: storage.clone(),
-        http_client,
+    tokio::spawn(crate::metrics::start(
+        vr_receiver,
+        storage.clone(),
+        config.clone(),
+        clock.clone(),
+    ));
+    tokio::spawn(crate::skew::run_sntp(config.clone()));
+    tokio::spawn(crate::finalizer::run(
+        storage.clone(),
+        config.clone(),
         vr_sender,
-    };
+        clock.clone(),
+    ));
+
+    let client = Arc::new(Client::new(
+        config,
+        storage.clone(),
+        AutoTxProbe {
+            http_clients: http_clients.clone(),
+        },
+        http_clients,
+        clock,
+    ));
+
+    let liveness_client = client.clone();
+    tokio::spawn(async move { liveness_client.run_liveness().await });
 
     loop {
         tokio::select! {
             _ = sender_interval.tick() => {
                 client.sender().await;
+                client.query().await;
+                client.scenario();
+                client.load_test();
             },
             _ = validator_interval.tick() => {
                 client.validator().await;
@@ -12,163 +12,527 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::clock::Clock;
+use crate::config::{AvailabilityRule, Config, TenantRule};
+use crate::finalizer::Watermark;
 use crate::record::VerifiedResult;
-use crate::time::{get_latest_finalized_minute, get_readable_time_from_minute, unix_now};
+use crate::time::{get_latest_finalized_bucket, get_readable_time_from_bucket, month_of};
 
+use chrono_tz::Tz;
 use color_eyre::eyre::Result;
 use flume::Receiver;
 use heck::ToSnakeCase;
 use prometheus::{
-    core::{AtomicU64, GenericCounter},
-    gather, register_int_counter, Encoder, TextEncoder,
+    gather, register, register_gauge_vec, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, GaugeVec, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
 };
 use reqwest::header::CONTENT_TYPE;
 use salvo::prelude::*;
 
-use std::collections::HashMap;
+use parking_lot::RwLock;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, LazyLock},
+};
+use tokio::time::{interval, Duration};
 
 use storage_dal::{Storage, StorageData};
 
+pub static QUERY_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "sla_query_latency_seconds",
+        "SLA test query probe latency(s)",
+        &["chain", "query"]
+    )
+    .unwrap()
+});
+
+pub static BLOCK_HEIGHT: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "sla_block_height",
+        "SLA test latest block height",
+        &["chain"]
+    )
+    .unwrap()
+});
+
+pub static BLOCK_LAG: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "sla_block_lag_seconds",
+        "SLA test time since the latest block(s)",
+        &["chain"]
+    )
+    .unwrap()
+});
+
+pub static BLOCK_STALLED: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "sla_block_stalled_seconds",
+        "SLA test time since the block height last advanced(s)",
+        &["chain"]
+    )
+    .unwrap()
+});
+
+pub static CERT_EXPIRY_DAYS: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "sla_cert_expiry_days",
+        "SLA test days until the server certificate expires",
+        &["chain", "endpoint"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_PHASE_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "sla_http_phase_seconds",
+        "SLA test latency(s) of each phase of calls, call is send, verify, load_send or load_verify",
+        &["chain", "call", "phase"]
+    )
+    .unwrap()
+});
+
+pub static PAYLOAD_RESULTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "sla_payload_results",
+        "SLA test results(time) of each payload, result is sent, sent_failed, succeed or failed",
+        &["chain", "payload", "result"]
+    )
+    .unwrap()
+});
+
+pub static PAYLOAD_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "sla_payload_latency_seconds",
+        "SLA test latency(s) of each payload, call is send or inclusion",
+        &["chain", "payload", "call"]
+    )
+    .unwrap()
+});
+
+pub static CLOCK_SKEW: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "sla_clock_skew_seconds",
+        "SLA test skew(s) of the local clock, source is a chain or sntp",
+        &["source"]
+    )
+    .unwrap()
+});
+
+pub static CLOCK_SKEW_EXCEEDED: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "sla_clock_skew_exceeded",
+        "SLA test 1 if the clock skew exceeds max_skew and buckets are not finalized"
+    )
+    .unwrap()
+});
+
+pub static MONTH_AVAILABILITY: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "sla_month_availability_ratio",
+        "SLA test availability of the calendar month of the latest counted bucket",
+        &["chain"]
+    )
+    .unwrap()
+});
+
 struct ChainCounter {
-    sent_failed_counter: GenericCounter<AtomicU64>,
-    unavailable_counter: GenericCounter<AtomicU64>,
-    observed_counter: GenericCounter<AtomicU64>,
+    sent_failed_counter: IntCounter,
+    unavailable_counter: IntCounter,
+    strict_unavailable_counter: IntCounter,
+    observed_counter: IntCounter,
+    no_data_counter: IntCounter,
+    /// Units in second, when the latest bucket counted ends
+    last_end: Option<u64>,
+    /// The observed and unavailable buckets of the calendar months of the counted
+    /// buckets, the latest two, the previous one still gets its late buckets
+    months: BTreeMap<(i32, u32), (u64, u64)>,
+}
+
+/// How a finalized bucket was counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Counted {
+    Available,
+    Unavailable,
+    SentFailed,
+    Excluded,
+}
+
+impl ChainCounter {
+    /// The counters of `chain_name`, not registered yet
+    fn new(chain_name: &str) -> Self {
+        let counter = |kind: &str, help: String| {
+            IntCounter::new(
+                format!("{}_{}_Counter", chain_name.to_snake_case(), kind),
+                help,
+            )
+            .unwrap()
+        };
+        Self {
+            sent_failed_counter: counter(
+                "Sent_failed",
+                format!("SLA test sent failed counter(time) for {}", chain_name),
+            ),
+            unavailable_counter: counter(
+                "Unavailable",
+                format!("SLA test unavailable counter(bucket) for {}", chain_name),
+            ),
+            strict_unavailable_counter: counter(
+                "Strict_Unavailable",
+                format!(
+                    "SLA test unavailable counter(bucket) without retries for {}",
+                    chain_name
+                ),
+            ),
+            observed_counter: counter(
+                "Observed",
+                format!("SLA test total observed counter(bucket) for {}", chain_name),
+            ),
+            no_data_counter: counter(
+                "No_Data",
+                format!("SLA test no data counter(bucket) for {}", chain_name),
+            ),
+            last_end: None,
+            months: BTreeMap::new(),
+        }
+    }
+
+    /// Export the counters
+    fn register(&self) {
+        for counter in [
+            &self.sent_failed_counter,
+            &self.unavailable_counter,
+            &self.strict_unavailable_counter,
+            &self.observed_counter,
+            &self.no_data_counter,
+        ] {
+            if let Err(e) = register(Box::new(counter.clone())) {
+                error!("register counter failed: {}", e);
+            }
+        }
+    }
+
+    fn count(
+        &mut self,
+        storage: &Storage,
+        series_rule: &SeriesRule,
+        vr: &VerifiedResult,
+    ) -> Counted {
+        self.last_end = self.last_end.max(Some(vr.end_secs()));
+        if vr.no_data {
+            self.no_data_counter.inc();
+        }
+        let Some((available, strictly_available)) = series_rule.availability(storage, vr) else {
+            return Counted::Excluded;
+        };
+        self.observed_counter.inc();
+        if !strictly_available {
+            self.strict_unavailable_counter.inc();
+        }
+        self.count_month(series_rule, vr, available);
+        if available {
+            Counted::Available
+        } else if vr.sent_failed_num != 0 {
+            self.sent_failed_counter.inc();
+            self.unavailable_counter.inc();
+            Counted::SentFailed
+        } else {
+            self.unavailable_counter.inc();
+            Counted::Unavailable
+        }
+    }
+
+    /// Count `vr` into the availability of the calendar month of its bucket
+    /// in the timezone of the series
+    fn count_month(&mut self, series_rule: &SeriesRule, vr: &VerifiedResult, available: bool) {
+        let month = month_of(vr.timestamp * vr.bucket_secs, series_rule.timezone);
+        let (observed, unavailable) = self.months.entry(month).or_default();
+        *observed += 1;
+        if !available {
+            *unavailable += 1;
+        }
+        // Older months are done, so are buckets of them counted late
+        while self.months.len() > 2 {
+            self.months.pop_first();
+        }
+        if let Some((observed, unavailable)) = self.months.values().next_back() {
+            MONTH_AVAILABILITY
+                .with_label_values(&[&vr.chain_name])
+                .set(1.0 - *unavailable as f64 / *observed as f64);
+        }
+    }
+
+    /// Count `vr` unless its bucket is counted already, then move the watermark to it
+    fn count_and_log(&mut self, storage: &Storage, series_rule: &SeriesRule, vr: &VerifiedResult) {
+        let readable_time =
+            get_readable_time_from_bucket(vr.timestamp, vr.bucket_secs, series_rule.timezone);
+        if self
+            .last_end
+            .is_some_and(|last_end| vr.end_secs() <= last_end)
+        {
+            debug!(
+                "{} already counted, VerifiedResult key: {}",
+                readable_time, vr.timestamp
+            );
+            return;
+        }
+        let counted = self.count(storage, series_rule, vr);
+        storage.insert(
+            &vr.chain_name,
+            Watermark {
+                name: vr.chain_name.clone(),
+                bucket: vr.timestamp,
+                bucket_secs: vr.bucket_secs,
+            },
+        );
+        match counted {
+            Counted::Available => info!(
+                "{} available, VerifiedResult key: {}",
+                readable_time, vr.timestamp
+            ),
+            Counted::SentFailed => warn!(
+                "{} sent_failed, VerifiedResult key: {}",
+                readable_time, vr.timestamp
+            ),
+            Counted::Unavailable => warn!(
+                "{} unavailable, VerifiedResult key: {}",
+                readable_time, vr.timestamp
+            ),
+            Counted::Excluded => info!(
+                "{} excluded, VerifiedResult key: {}",
+                readable_time, vr.timestamp
+            ),
+        }
+        if vr.no_data {
+            warn!(
+                "{} no data of {}, VerifiedResult key: {}",
+                readable_time, vr.chain_name, vr.timestamp
+            );
+        }
+    }
+}
+
+/// How the buckets of a series are classified
+#[derive(Debug, Clone)]
+pub(crate) struct SeriesRule {
+    rule: AvailabilityRule,
+    /// The tenant series deciding a chain series by `tenant_rule`
+    tenants: Vec<String>,
+    tenant_rule: TenantRule,
+    /// Units in second
+    pub bucket_secs: u64,
+    pub timezone: Tz,
+}
+
+impl SeriesRule {
+    /// The rule of the series `name` in `config`, the default one if it is removed
+    pub fn of(config: &Config, name: &str) -> Self {
+        let chain_sender = config
+            .chain_sender_vec
+            .iter()
+            .find(|chain_sender| chain_sender.series_names().iter().any(|n| n == name))
+            .cloned()
+            .unwrap_or_default();
+        Self {
+            rule: chain_sender.availability.clone(),
+            tenants: if name == chain_sender.chain_name {
+                chain_sender.tenant_names()
+            } else {
+                vec![]
+            },
+            tenant_rule: chain_sender.tenant_rule,
+            bucket_secs: chain_sender.bucket_secs,
+            timezone: config.timezone_of(&chain_sender),
+        }
+    }
+
+    /// Whether the bucket of `vr` is available and strictly available,
+    /// `None` if it is excluded
+    pub fn availability(&self, storage: &Storage, vr: &VerifiedResult) -> Option<(bool, bool)> {
+        if self.tenants.is_empty() {
+            return vr.availability(&self.rule);
+        }
+        let (total, available, strictly_available) = self
+            .tenants
+            .iter()
+            .filter_map(|name| storage.get::<VerifiedResult>(&format!("{}/{}", name, vr.timestamp)))
+            .filter_map(|tenant_vr| tenant_vr.availability(&self.rule))
+            .fold(
+                (0, 0, 0),
+                |(total, available, strictly_available), (tenant_available, tenant_strict)| {
+                    (
+                        total + 1,
+                        available + usize::from(tenant_available),
+                        strictly_available + usize::from(tenant_strict),
+                    )
+                },
+            );
+        if total == 0 {
+            return vr.availability(&self.rule);
+        }
+        Some((
+            self.tenant_rule.is_met(available, total),
+            self.tenant_rule.is_met(strictly_available, total),
+        ))
+    }
+}
+
+/// The counters of every series
+pub(crate) struct Counting {
+    storage: Storage,
+    /// Whether the counters are registered to be exported
+    export: bool,
+    chain_counter_map: HashMap<String, ChainCounter>,
+}
+
+impl Counting {
+    pub fn new(storage: Storage, export: bool) -> Self {
+        Self {
+            storage,
+            export,
+            chain_counter_map: HashMap::new(),
+        }
+    }
+
+    /// Register and recover the counters of every series in `config` not added yet,
+    /// `now` is in ms
+    pub fn add_configured(&mut self, config: &Config, now: u64) {
+        for chain_sender in &config.chain_sender_vec {
+            for name in chain_sender.series_names() {
+                self.counter(config, &name, now);
+            }
+        }
+    }
+
+    /// Count `vr` emitted by the finalizer, `now` is in ms
+    pub fn count(&mut self, config: &Config, vr: &VerifiedResult, now: u64) {
+        let series_rule = SeriesRule::of(config, &vr.chain_name);
+        let storage = self.storage.clone();
+        self.counter(config, &vr.chain_name, now)
+            .count_and_log(&storage, &series_rule, vr);
+    }
+
+    /// The observed, unavailable and no data buckets counted of the series `name`
+    #[cfg(test)]
+    pub fn counted(&self, name: &str) -> (u64, u64, u64) {
+        self.chain_counter_map
+            .get(name)
+            .map_or((0, 0, 0), |chain_counter| {
+                (
+                    chain_counter.observed_counter.get(),
+                    chain_counter.unavailable_counter.get(),
+                    chain_counter.no_data_counter.get(),
+                )
+            })
+    }
+
+    /// The counters of the series `name`, added if they are not yet
+    fn counter(&mut self, config: &Config, name: &str, now: u64) -> &mut ChainCounter {
+        let storage = &self.storage;
+        let export = self.export;
+        self.chain_counter_map
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                let mut chain_counter = ChainCounter::new(name);
+                if export {
+                    chain_counter.register();
+                }
+                recover_data(
+                    &mut chain_counter,
+                    storage,
+                    &SeriesRule::of(config, name),
+                    config.validator_timeout,
+                    name,
+                    now,
+                );
+                chain_counter
+            })
+    }
 }
 
+/// Count the buckets emitted by the finalizer, the counters of every configured
+/// series are registered and recovered at start and after a reload
 pub async fn start(
     vr_receiver: Receiver<VerifiedResult>,
     storage: Storage,
-    check_timeout: u64,
-    chain_for_send: Vec<String>,
+    config: Arc<RwLock<Config>>,
+    clock: Arc<dyn Clock>,
 ) {
-    // sent_failed < unavailable < observed
+    // sent_failed < unavailable < strict_unavailable < observed
     info!("metrics start observing");
-    let mut chain_counter_map: HashMap<String, ChainCounter> = HashMap::new();
-    for chain_name in chain_for_send {
-        let sent_failed_counter = register_int_counter!(
-            format!("{}_Sent_failed_Counter", chain_name.to_snake_case()),
-            format!("SLA test sent failed counter(time) for {}", chain_name)
-        )
-        .unwrap();
-        let unavailable_counter = register_int_counter!(
-            format!("{}_Unavailable_Counter", chain_name.to_snake_case()),
-            format!("SLA test unavailable counter(min) for {}", chain_name)
-        )
-        .unwrap();
-        let observed_counter = register_int_counter!(
-            format!("{}_Observed_Counter", chain_name.to_snake_case()),
-            format!("SLA test total observed counter(min) for {}", chain_name)
-        )
-        .unwrap();
-        recover_data(
-            &sent_failed_counter,
-            &unavailable_counter,
-            &observed_counter,
-            storage.clone(),
-            check_timeout,
-            chain_name.clone(),
-        );
-        chain_counter_map.insert(
-            chain_name,
-            ChainCounter {
-                sent_failed_counter,
-                unavailable_counter,
-                observed_counter,
-            },
-        );
-    }
+    let mut counting = Counting::new(storage, true);
+    // Series added by a reload are picked up on the next tick
+    let mut ticker = interval(Duration::from_secs(1));
     loop {
-        if let Ok(vr) = vr_receiver.recv() {
-            let ChainCounter {
-                sent_failed_counter,
-                unavailable_counter,
-                observed_counter,
-            } = chain_counter_map
-                .get_mut(&vr.chain_name)
-                .unwrap_or_else(|| {
-                    panic!(
-                        "chain_counter_map get failed, chain_name: {}",
-                        vr.chain_name
-                    )
-                });
-            observed_counter.inc();
-            if vr.sent_failed_num != 0 {
-                warn!(
-                    "{} sent_failed, VerifiedResult key: {}",
-                    get_readable_time_from_minute(vr.timestamp),
-                    vr.timestamp
-                );
-                sent_failed_counter.inc();
-                unavailable_counter.inc()
-            } else if vr.failed_num != 0 {
-                warn!(
-                    "{} unavailable, VerifiedResult key: {}",
-                    get_readable_time_from_minute(vr.timestamp),
-                    vr.timestamp
-                );
-                unavailable_counter.inc()
-            } else {
-                info!(
-                    "{} available, VerifiedResult key: {}",
-                    get_readable_time_from_minute(vr.timestamp),
-                    vr.timestamp
-                );
+        tokio::select! {
+            _ = ticker.tick() => {
+                counting.add_configured(&config.read(), clock.now());
+            }
+            vr = vr_receiver.recv_async() => {
+                let Ok(vr) = vr else {
+                    break;
+                };
+                counting.count(&config.read(), &vr, clock.now());
             }
         }
     }
 }
 
+/// Count the buckets up to the watermark, the later ones are emitted by the finalizer,
+/// a series without one gets it at the latest finalized bucket
 fn recover_data(
-    sent_failed_counter: &GenericCounter<AtomicU64>,
-    unavailable_counter: &GenericCounter<AtomicU64>,
-    observed_counter: &GenericCounter<AtomicU64>,
-    storage: Storage,
+    chain_counter: &mut ChainCounter,
+    storage: &Storage,
+    series_rule: &SeriesRule,
     check_timeout: u64,
-    chain_name: String,
+    chain_name: &str,
+    now: u64,
 ) {
-    let finalized_minute = get_latest_finalized_minute(unix_now(), check_timeout);
-    let (sent_failed, unavailable, observed) = storage
-        .op
-        .blocking()
-        .lister(&format!(
-            "STRUCTURED/{}/{}/",
-            VerifiedResult::name(),
-            chain_name
-        ))
-        .unwrap()
-        .fold(
-            (0, 0, 0),
-            |(mut sent_failed, mut unavailable, mut observed), vr| {
-                if let Ok(vr_entry) = vr {
-                    if let Some(vr) = storage.get_by_path::<VerifiedResult>(vr_entry.path()) {
-                        if vr.timestamp <= finalized_minute {
-                            observed += 1;
-                            if vr.sent_failed_num != 0 || vr.failed_num != 0 {
-                                unavailable += 1;
-                                if vr.sent_failed_num != 0 {
-                                    sent_failed += 1;
-                                }
-                            };
-                        }
-                        return (sent_failed, unavailable, observed);
-                    }
-                }
-                (0, 0, 0)
-            },
-        );
-    sent_failed_counter.inc_by(sent_failed);
-    unavailable_counter.inc_by(unavailable);
-    observed_counter.inc_by(observed);
+    // The only place a watermark is created, the finalizer emits the buckets after it
+    let watermark = storage.get::<Watermark>(chain_name).unwrap_or_else(|| {
+        let watermark = Watermark {
+            name: chain_name.to_string(),
+            bucket: get_latest_finalized_bucket(now, check_timeout, series_rule.bucket_secs),
+            bucket_secs: series_rule.bucket_secs,
+        };
+        storage.insert(chain_name, watermark.clone());
+        watermark
+    });
+    for vr in stored_vrs(storage, chain_name) {
+        if vr.end_secs() <= watermark.end_secs() {
+            chain_counter.count(storage, series_rule, &vr);
+        }
+    }
+    chain_counter.last_end = Some(watermark.end_secs());
     info!(
-        "recover metrics data before({}): {}, sent_failed: {}, unavailable: {}, observed: {}",
+        "recover metrics data before({}): {}, sent_failed: {}, unavailable: {}, strict_unavailable: {}, observed: {}, no_data: {}",
         chain_name,
-        get_readable_time_from_minute(finalized_minute),
-        sent_failed,
-        unavailable,
-        observed
+        get_readable_time_from_bucket(
+            watermark.bucket,
+            watermark.bucket_secs,
+            series_rule.timezone
+        ),
+        chain_counter.sent_failed_counter.get(),
+        chain_counter.unavailable_counter.get(),
+        chain_counter.strict_unavailable_counter.get(),
+        chain_counter.observed_counter.get(),
+        chain_counter.no_data_counter.get(),
     );
 }
 
+/// Every bucket of the series `name` in storage
+pub fn stored_vrs(storage: &Storage, name: &str) -> Vec<VerifiedResult> {
+    storage
+        .op
+        .blocking()
+        .lister(&format!("STRUCTURED/{}/{}/", VerifiedResult::name(), name))
+        .unwrap()
+        .flatten()
+        .filter_map(|vr_entry| storage.get_by_path::<VerifiedResult>(vr_entry.path()))
+        .collect()
+}
+
 pub async fn run_metrics_exporter(port: u16, rx: Receiver<()>) -> Result<()> {
     let router = Router::new().push(Router::with_path("metrics").get(metrics));
 
@@ -0,0 +1,411 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::Config;
+use color_eyre::eyre::{eyre, Result};
+use heck::ToSnakeCase;
+use serde_json::Value;
+use std::fmt;
+
+/// Environment variables overriding config values, e.g.
+/// `SLA_CLIENT__STORAGE_PATH` or `SLA_CLIENT__CHAIN_SENDER_VEC__CITA_TEST__SENDER_URL`.
+/// Ends with "__" like the separator of keys, so the `SLA_CLIENT_PORT` and alike
+/// Kubernetes sets for a Service named sla-client are not taken
+const ENV_PREFIX: &str = "SLA_CLIENT__";
+
+/// Where a value of the effective config is set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    /// A file of `chain_sender_dir`
+    ChainFile(String),
+    /// Named by the environment variable
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::File => write!(f, "file"),
+            Self::ChainFile(path) => write!(f, "file {}", path),
+            Self::Env(name) => write!(f, "env {}", name),
+            Self::Cli => write!(f, "cli --set"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Override {
+    /// Keys of tables, and indexes or names of array elements
+    path: Vec<String>,
+    value: String,
+    source: Source,
+}
+
+/// Values set over the config file, by `SLA_CLIENT__*` environment variables
+/// and then by the command line. Reapplied whenever the file is reloaded.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides(Vec<Override>);
+
+impl Overrides {
+    /// `SLA_CLIENT__*` environment variables with "__" between keys, then
+    /// `sets` of "path=value" with "." between keys
+    pub fn new(sets: &[String]) -> Result<Self> {
+        Self::with_env(std::env::vars(), sets)
+    }
+
+    /// Like `new`, with the environment variables `env`
+    fn with_env(env: impl Iterator<Item = (String, String)>, sets: &[String]) -> Result<Self> {
+        let mut env: Vec<(String, String)> = env
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        env.sort();
+        let mut overrides: Vec<Override> = env
+            .into_iter()
+            .map(|(name, value)| Override {
+                path: name[ENV_PREFIX.len()..]
+                    .to_lowercase()
+                    .split("__")
+                    .map(str::to_string)
+                    .collect(),
+                value,
+                source: Source::Env(name),
+            })
+            .collect();
+        for set in sets {
+            let (path, value) = set
+                .split_once('=')
+                .ok_or_else(|| eyre!("invalid --set '{}', expected path=value", set))?;
+            overrides.push(Override {
+                path: path.split('.').map(str::to_string).collect(),
+                value: value.to_string(),
+                source: Source::Cli,
+            });
+        }
+        Ok(Self(overrides))
+    }
+
+    /// The last value set for the top level `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .rev()
+            .find(|o| o.path.len() == 1 && o.path[0].eq_ignore_ascii_case(key))
+            .map(|o| o.value.as_str())
+    }
+
+    /// `config` with every override applied in order
+    pub fn apply(&self, config: &Config) -> Result<Config> {
+        let mut value = serde_json::to_value(config)?;
+        for o in &self.0 {
+            let pointer = resolve(&value, &o.path)
+                .map(|path| pointer(&path))
+                .ok_or_else(|| eyre!("{}: no config value at '{}'", o.source, o.path.join(".")))?;
+            let leaf = value.pointer_mut(&pointer).unwrap();
+            *leaf = match leaf {
+                // Strings are taken as is, so that e.g. numeric secrets stay strings
+                Value::String(_) => Value::String(o.value.clone()),
+                _ => serde_json::from_str(&o.value)
+                    .unwrap_or_else(|_| Value::String(o.value.clone())),
+            };
+            serde_json::from_value::<Config>(value.clone()).map_err(|e| {
+                eyre!(
+                    "{}: invalid value '{}' at '{}': {}",
+                    o.source,
+                    o.value,
+                    o.path.join("."),
+                    e
+                )
+            })?;
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Every value of `config` as "path = value (source)", `file` is the table
+    /// loaded from the config file and `chain_files` the tables of the files
+    /// of `chain_sender_dir` by path. Secret values are masked.
+    pub fn describe(
+        &self,
+        config: &Config,
+        file: &Value,
+        chain_files: &[(String, Value)],
+    ) -> Result<Vec<String>> {
+        let value = serde_json::to_value(config)?;
+        let overridden: Vec<(Vec<String>, &Source)> = self
+            .0
+            .iter()
+            .filter_map(|o| Some((resolve(&value, &o.path)?, &o.source)))
+            .collect();
+        // The chains of `chain_files` follow those of `file`
+        let file_chains = file["chain_sender_vec"].as_array().map_or(0, Vec::len);
+        let mut lines = vec![];
+        for_each_leaf(&value, &mut vec![], &mut |path, leaf| {
+            let source = overridden
+                .iter()
+                .rev()
+                .find(|(overridden_path, _)| path.starts_with(overridden_path))
+                .map(|(_, source)| (*source).clone())
+                .unwrap_or_else(|| {
+                    let chain_file = match path {
+                        [key, index, rest @ ..] if key == "chain_sender_vec" => index
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|index| chain_files.get(index.checked_sub(file_chains)?))
+                            .map(|(chain_path, chain_file)| (chain_path, chain_file, rest)),
+                        _ => None,
+                    };
+                    match chain_file {
+                        Some((chain_path, chain_file, rest)) => {
+                            if chain_file.pointer(&pointer(rest)).is_some() {
+                                Source::ChainFile(chain_path.clone())
+                            } else {
+                                Source::Default
+                            }
+                        }
+                        None if file.pointer(&pointer(path)).is_some() => Source::File,
+                        None => Source::Default,
+                    }
+                });
+            let secret = path.iter().any(|key| key == "auth")
+                && path.last().is_some_and(|key| key == "value")
+                && leaf.as_str().is_some_and(|leaf| !leaf.is_empty());
+            lines.push(format!(
+                "{} = {} ({})",
+                path.join("."),
+                if secret {
+                    "\"***\"".to_string()
+                } else {
+                    leaf.to_string()
+                },
+                source
+            ));
+        });
+        Ok(lines)
+    }
+}
+
+/// The path of `value` at `path`, array elements may be named by their
+/// `chain_name` or `name` in any case
+fn resolve(value: &Value, path: &[String]) -> Option<Vec<String>> {
+    let Some((key, rest)) = path.split_first() else {
+        return Some(vec![]);
+    };
+    let (resolved, child) = match value {
+        Value::Object(table) => table
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(name, child)| (name.clone(), child))?,
+        Value::Array(elements) => match key.parse::<usize>() {
+            Ok(index) => (index.to_string(), elements.get(index)?),
+            Err(_) => elements
+                .iter()
+                .enumerate()
+                .find(|(_, element)| {
+                    ["chain_name", "name"].iter().any(|name_key| {
+                        element[name_key]
+                            .as_str()
+                            .is_some_and(|name| name.to_snake_case() == key.to_snake_case())
+                    })
+                })
+                .map(|(index, element)| (index.to_string(), element))?,
+        },
+        _ => return None,
+    };
+    let mut resolved_path = vec![resolved];
+    resolved_path.extend(resolve(child, rest)?);
+    Some(resolved_path)
+}
+
+/// JSON pointer of the resolved `path`
+fn pointer(path: &[String]) -> String {
+    path.iter()
+        .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+fn for_each_leaf(value: &Value, path: &mut Vec<String>, f: &mut impl FnMut(&[String], &Value)) {
+    match value {
+        Value::Object(table) if !table.is_empty() => {
+            for (key, child) in table {
+                path.push(key.clone());
+                for_each_leaf(child, path, f);
+                path.pop();
+            }
+        }
+        Value::Array(elements) if !elements.is_empty() => {
+            for (index, child) in elements.iter().enumerate() {
+                path.push(index.to_string());
+                for_each_leaf(child, path, f);
+                path.pop();
+            }
+        }
+        _ => f(path, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{Auth, Secret},
+        client::tests::chain_sender,
+        config::ChainSender,
+    };
+    use serde_json::json;
+
+    fn overrides(env: &[(&str, &str)], sets: &[&str]) -> Overrides {
+        Overrides::with_env(
+            env.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+            &sets.iter().map(|set| set.to_string()).collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    fn config() -> Config {
+        Config {
+            chain_sender_vec: vec![
+                ChainSender {
+                    auth: Auth::Bearer {
+                        token: Secret {
+                            value: "token".to_string(),
+                            ..Default::default()
+                        },
+                    },
+                    ..chain_sender("CITA-Test")
+                },
+                chain_sender("dir"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cli_overrides_env() {
+        let overrides = overrides(
+            &[
+                ("SLA_CLIENT__SENDER_INTERVAL", "40"),
+                ("SLA_CLIENT_PORT", "tcp://10.0.0.1:61616"),
+            ],
+            &["sender_interval=50"],
+        );
+        assert_eq!(overrides.get("sender_interval"), Some("50"));
+        assert_eq!(overrides.get("port"), None);
+        assert_eq!(overrides.apply(&config()).unwrap().sender_interval, 50);
+    }
+
+    #[test]
+    fn env_keys_are_split_at_double_underscores() {
+        let overrides = overrides(
+            &[(
+                "SLA_CLIENT__CHAIN_SENDER_VEC__CITA_TEST__SENDER_URL",
+                "http://other/send_tx",
+            )],
+            &[],
+        );
+        assert_eq!(
+            overrides.0[0].path,
+            ["chain_sender_vec", "cita_test", "sender_url"]
+        );
+        let config = overrides.apply(&config()).unwrap();
+        assert_eq!(
+            config.chain_sender_vec[0].sender_url,
+            "http://other/send_tx"
+        );
+    }
+
+    #[test]
+    fn elements_are_named_by_their_snake_cased_name() {
+        let config = config();
+        let value = serde_json::to_value(&config).unwrap();
+        for name in ["cita_test", "CITA-Test", "cita-test", "0"] {
+            let path: Vec<String> = ["chain_sender_vec", name, "bucket_secs"]
+                .iter()
+                .map(|key| key.to_string())
+                .collect();
+            assert_eq!(
+                resolve(&value, &path),
+                Some(vec![
+                    "chain_sender_vec".to_string(),
+                    "0".to_string(),
+                    "bucket_secs".to_string()
+                ]),
+                "{}",
+                name
+            );
+        }
+        let path = vec!["chain_sender_vec".to_string(), "missing".to_string()];
+        assert_eq!(resolve(&value, &path), None);
+    }
+
+    #[test]
+    fn strings_are_kept_and_other_values_parsed() {
+        let config = overrides(
+            &[],
+            &[
+                "chain_sender_vec.cita_test.auth.token.value=123456",
+                "chain_sender_vec.dir.bucket_secs=120",
+                "chain_sender_vec.dir.user_code=007",
+            ],
+        )
+        .apply(&config())
+        .unwrap();
+        let Auth::Bearer { token } = &config.chain_sender_vec[0].auth else {
+            panic!("auth is not bearer");
+        };
+        assert_eq!(token.value, "123456");
+        assert_eq!(config.chain_sender_vec[1].bucket_secs, 120);
+        assert_eq!(config.chain_sender_vec[1].user_code, "007");
+
+        let invalid = overrides(&[], &["sender_interval=soon"]).apply(&config);
+        assert!(invalid
+            .unwrap_err()
+            .to_string()
+            .starts_with("cli --set: invalid value 'soon' at 'sender_interval'"));
+    }
+
+    #[test]
+    fn describe_names_the_source_and_masks_secrets() {
+        let file = json!({
+            "sender_interval": 30,
+            "chain_sender_vec": [{
+                "chain_name": "CITA-Test",
+                "auth": {"mode": "bearer", "token": {"value": "token"}},
+            }],
+        });
+        let chain_files = vec![(
+            "chains/dir.toml".to_string(),
+            json!({"chain_name": "dir", "sender_url": "http://gateway/send_tx"}),
+        )];
+        let overrides = overrides(
+            &[("SLA_CLIENT__STORAGE_PATH", "env_db")],
+            &["validator_interval=20"],
+        );
+        let config = overrides.apply(&config()).unwrap();
+        let lines = overrides.describe(&config, &file, &chain_files).unwrap();
+        for line in [
+            "sender_interval = 30 (file)",
+            "chain_sender_vec.0.auth.token.value = \"***\" (file)",
+            "chain_sender_vec.1.sender_url = \"http://gateway/send_tx\" (file chains/dir.toml)",
+            "chain_sender_vec.1.bucket_secs = 60 (default)",
+            "storage_path = \"env_db\" (env SLA_CLIENT__STORAGE_PATH)",
+            "validator_interval = 20 (cli --set)",
+        ] {
+            assert!(lines.iter().any(|l| l == line), "{} in {:#?}", line, lines);
+        }
+    }
+}
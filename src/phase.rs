@@ -0,0 +1,135 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::{HttpConfig, TlsConfig},
+    metrics::HTTP_PHASE_LATENCY,
+};
+use color_eyre::eyre::{eyre, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{fs, time::Duration};
+use tokio::{
+    net::{lookup_host, TcpStream},
+    time::{timeout, Instant},
+};
+use tokio_native_tls::{native_tls, TlsConnector};
+
+/// Durations of the phases of an http call, units in ms, `None` if not reached.
+///
+/// The http client reuses its connections, so `dns`, `connect` and `tls` are
+/// measured on a fresh connection opened to the same endpoint right before
+/// the call.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Phases {
+    pub dns: Option<f64>,
+    pub connect: Option<f64>,
+    pub tls: Option<f64>,
+    /// From sending the request to receiving the response headers
+    pub ttfb: Option<f64>,
+    /// Reading and decoding the response body
+    pub body: Option<f64>,
+}
+
+impl Phases {
+    /// Export the measured phases of `call` to `chain_name`, e.g. "send" or "verify"
+    pub fn observe(&self, chain_name: &str, call: &str) {
+        for (phase, duration) in [
+            ("dns", self.dns),
+            ("connect", self.connect),
+            ("tls", self.tls),
+            ("ttfb", self.ttfb),
+            ("body", self.body),
+        ] {
+            if let Some(duration) = duration {
+                HTTP_PHASE_LATENCY
+                    .with_label_values(&[chain_name, call, phase])
+                    .observe(duration / 1000.0);
+            }
+        }
+    }
+}
+
+pub fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Measure `dns`, `connect` and `tls` of `url`, each within `http.connect_timeout`.
+/// Nothing is measured through a proxy, the calls would not take that path.
+pub async fn measure_connect(url: &str, http: &HttpConfig, tls: &TlsConfig) -> Phases {
+    let mut phases = Phases::default();
+    if !http.proxy.is_empty() {
+        return phases;
+    }
+    let connect_timeout = Duration::from_secs(http.connect_timeout);
+    if let Err(e) = try_measure_connect(url, connect_timeout, tls, &mut phases).await {
+        debug!("measure connect to '{}' failed: {}", url, e);
+    }
+    phases
+}
+
+async fn try_measure_connect(
+    url: &str,
+    connect_timeout: Duration,
+    tls: &TlsConfig,
+    phases: &mut Phases,
+) -> Result<()> {
+    let url = Url::parse(url)?;
+    let host = url.host_str().ok_or_else(|| eyre!("no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| eyre!("no port"))?;
+
+    let start = Instant::now();
+    let addr = if !tls.resolve_host.is_empty() && host == tls.resolve_host {
+        tls.resolve_addr.parse()?
+    } else {
+        timeout(connect_timeout, lookup_host((host, port)))
+            .await??
+            .next()
+            .ok_or_else(|| eyre!("'{}' resolved to nothing", host))?
+    };
+    phases.dns = Some(elapsed_ms(start));
+
+    let start = Instant::now();
+    let stream = timeout(connect_timeout, TcpStream::connect(addr)).await??;
+    phases.connect = Some(elapsed_ms(start));
+
+    if url.scheme() == "https" {
+        let connector = tls_connector(tls)?;
+        let start = Instant::now();
+        timeout(connect_timeout, connector.connect(host, stream)).await??;
+        phases.tls = Some(elapsed_ms(start));
+    }
+    Ok(())
+}
+
+fn tls_connector(tls: &TlsConfig) -> Result<TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+    if !tls.ca_file.is_empty() {
+        const END: &str = "-----END CERTIFICATE-----";
+        let bundle = fs::read_to_string(&tls.ca_file)?;
+        for pem in bundle.split_inclusive(END).filter(|pem| pem.contains(END)) {
+            builder.add_root_certificate(native_tls::Certificate::from_pem(pem.as_bytes())?);
+        }
+    }
+    if !tls.cert_file.is_empty() {
+        builder.identity(native_tls::Identity::from_pkcs8(
+            &fs::read(&tls.cert_file)?,
+            &fs::read(&tls.key_file)?,
+        )?);
+    }
+    Ok(builder.build()?.into())
+}
@@ -0,0 +1,497 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    clock::Clock,
+    config::{ChainSender, Config, Payload, QueryProbe},
+    http::{observe_cert_expiry, HttpClients},
+    phase::{elapsed_ms, measure_connect},
+    record::{ErrorKind, Record, UnverifiedTX},
+    skew::observe_date_skew,
+    template::render,
+};
+use serde_json::Value;
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::time::{sleep, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Succeed,
+    Failed,
+}
+
+/// A strategy for sending probes to a chain and checking their results.
+///
+/// `Client` only drives the scheduling and the minute bookkeeping, everything
+/// that depends on the gateway or chain type lives behind this trait.
+pub(crate) trait Probe {
+    /// Send the probe of `chain_sender`, filling `record` with the response.
+    /// Returns the tx hash to verify later.
+    fn submit(
+        &self,
+        chain_sender: &ChainSender,
+        record: &mut Record,
+    ) -> impl Future<Output = Option<String>> + Send;
+
+    /// Query the onchain result of `utx` sent by `chain_sender`, filling `record`
+    /// with the response.
+    fn verify(
+        &self,
+        chain_sender: &ChainSender,
+        utx: &UnverifiedTX,
+        record: &mut Record,
+    ) -> impl Future<Output = ()> + Send;
+
+    /// Decide whether the call recorded in `record` succeeded.
+    fn classify(&self, record: &Record) -> Outcome;
+}
+
+/// Run `attempt` with the retry policy of `chain_sender` until it succeeds or the
+/// policy gives up, the phases of every attempt are exported as `call`.
+/// `attempt` gets `record` and hands it back with its result.
+async fn with_retry<P, T, F, Fut>(
+    probe: &P,
+    chain_sender: &ChainSender,
+    record: &mut Record,
+    call: &str,
+    mut attempt: F,
+) -> T
+where
+    P: Probe,
+    F: FnMut(Record) -> Fut,
+    Fut: Future<Output = (Record, T)>,
+{
+    loop {
+        record.next_attempt();
+        let (attempted, result) = attempt(std::mem::take(record)).await;
+        *record = attempted;
+        record.phases.observe(&chain_sender.chain_name, call);
+        if probe.classify(record) == Outcome::Succeed {
+            return result;
+        }
+        record.error.get_or_insert(ErrorKind::Status);
+        let Some(backoff) = chain_sender.retry.backoff(record) else {
+            return result;
+        };
+        warn!(
+            "retry '{}' after {:?}, attempt {} failed: {:?}",
+            &record.api, backoff, record.attempts, record.error
+        );
+        sleep(backoff).await;
+    }
+}
+
+/// Send `payload` to `chain_sender` with `user_code` under its retry policy.
+/// Returns the record of the last attempt, and the tx to verify if it is sent.
+pub async fn send_tx<P: Probe>(
+    probe: &P,
+    clock: &dyn Clock,
+    chain_sender: &ChainSender,
+    user_code: String,
+    payload: Payload,
+    call: &str,
+) -> (Record, Option<UnverifiedTX>) {
+    let mut record = Record {
+        timestamp: clock.now(),
+        api: chain_sender.sender_url.clone(),
+        data: render(&payload.data, &chain_sender.template_vars()),
+        payload: payload.name,
+        user_code,
+        ..Default::default()
+    };
+    let utx = with_retry(
+        probe,
+        chain_sender,
+        &mut record,
+        call,
+        |mut record| async move {
+            let tx_hash = probe.submit(chain_sender, &mut record).await;
+            (record, tx_hash)
+        },
+    )
+    .await
+    .map(|tx_hash| UnverifiedTX {
+        tx_hash,
+        chain_name: chain_sender.chain_name.clone(),
+        sent_timestamp: record.timestamp,
+        user_code: record.user_code.clone(),
+        payload: record.payload.clone(),
+        retried: record.attempts > 1,
+    });
+    (record, utx)
+}
+
+/// Verify `utx` once under the retry policy of `chain_sender`, returns the
+/// record of the last attempt
+pub async fn verify_tx<P: Probe>(
+    probe: &P,
+    clock: &dyn Clock,
+    chain_sender: &ChainSender,
+    utx: &UnverifiedTX,
+    verify_api_url: &str,
+    call: &str,
+) -> Record {
+    let mut record = Record {
+        timestamp: clock.now(),
+        api: verify_api_url.to_string(),
+        user_code: utx.user_code.clone(),
+        ..Default::default()
+    };
+    with_retry(
+        probe,
+        chain_sender,
+        &mut record,
+        call,
+        |mut record| async move {
+            probe.verify(chain_sender, utx, &mut record).await;
+            (record, ())
+        },
+    )
+    .await;
+    record
+}
+
+/// Units in ms, between the first two verifies of a tx waited for
+const VERIFY_POLL: u64 = 1000;
+/// Units in ms, the poll is doubled up to it
+const MAX_VERIFY_POLL: u64 = 10_000;
+
+/// Verify `utx` until it succeeds or `validator_timeout` after it was sent,
+/// polling less often the longer it takes. Returns the inclusion latency.
+pub async fn wait_verified<P: Probe>(
+    probe: &P,
+    clock: &dyn Clock,
+    chain_sender: &ChainSender,
+    utx: &UnverifiedTX,
+    verify_api_url: &str,
+    validator_timeout: u64,
+    call: &str,
+) -> Option<u64> {
+    let mut poll = VERIFY_POLL;
+    while clock.now() - utx.sent_timestamp <= validator_timeout * 1000 {
+        let record = verify_tx(probe, clock, chain_sender, utx, verify_api_url, call).await;
+        if probe.classify(&record) == Outcome::Succeed {
+            return Some(clock.now() - utx.sent_timestamp);
+        }
+        sleep(Duration::from_millis(poll)).await;
+        poll = (poll * 2).min(MAX_VERIFY_POLL);
+    }
+    None
+}
+
+/// Send with every user code of the chains in `chains`, or of all chains if
+/// it is empty, and wait for the sent txs to be verified. Prints every outcome
+/// and returns whether all of them succeeded.
+pub async fn probe_once(config: &Config, clock: &dyn Clock, chains: &[String]) -> bool {
+    let probe = AutoTxProbe {
+        http_clients: Arc::new(HttpClients::default()),
+    };
+    let mut all_succeed = true;
+    for chain_sender in config
+        .chain_sender_vec
+        .iter()
+        .filter(|chain_sender| chains.is_empty() || chains.contains(&chain_sender.chain_name))
+    {
+        for user_code in chain_sender.user_codes() {
+            let (record, utx) = send_tx(
+                &probe,
+                clock,
+                chain_sender,
+                user_code,
+                chain_sender.pick_payload(0),
+                "send",
+            )
+            .await;
+            let send_latency = clock.now() - record.timestamp;
+            let Some(utx) = utx else {
+                all_succeed = false;
+                println!(
+                    "{} {} {}: sent_failed after {} attempt(s) in {}ms, status: {}, error: {:?}",
+                    chain_sender.chain_name,
+                    record.user_code,
+                    record.payload,
+                    record.attempts,
+                    send_latency,
+                    record.status,
+                    record.error
+                );
+                continue;
+            };
+            let verified = wait_verified(
+                &probe,
+                clock,
+                chain_sender,
+                &utx,
+                &config.verify_api_url,
+                config.validator_timeout,
+                "verify",
+            )
+            .await;
+            match verified {
+                Some(inclusion_latency) => println!(
+                    "{} {} {}: succeed, tx {}, sent in {}ms, included in {}ms",
+                    utx.chain_name,
+                    utx.user_code,
+                    utx.payload,
+                    utx.tx_hash,
+                    send_latency,
+                    inclusion_latency
+                ),
+                None => {
+                    all_succeed = false;
+                    println!(
+                        "{} {} {}: failed, tx {} not included within {}s",
+                        utx.chain_name,
+                        utx.user_code,
+                        utx.payload,
+                        utx.tx_hash,
+                        config.validator_timeout
+                    );
+                }
+            }
+        }
+    }
+    all_succeed
+}
+
+/// Probe against the auto_tx gateway.
+#[derive(Debug, Clone)]
+pub struct AutoTxProbe {
+    pub http_clients: Arc<HttpClients>,
+}
+
+impl AutoTxProbe {
+    /// Call `record.api` with `headers`, a POST of `record.data` or a GET when it is
+    /// empty, and add the decoded response or the error to `record`
+    async fn send_and_decode(
+        &self,
+        chain_sender: &ChainSender,
+        record: &mut Record,
+        headers: &[(&str, &str)],
+    ) {
+        let request = match self
+            .http_clients
+            .request(chain_sender, &record.api, &record.data, headers)
+            .await
+        {
+            Ok(request) => request,
+            Err(e) => {
+                error!("{}", e);
+                record.error = Some(ErrorKind::Other);
+                return;
+            }
+        };
+        if chain_sender.http.phase_timing {
+            record.phases =
+                measure_connect(&record.api, &chain_sender.http, &chain_sender.tls).await;
+        }
+        let start = Instant::now();
+        match request.send().await {
+            Ok(resp) => {
+                record.phases.ttfb = Some(elapsed_ms(start));
+                let start = Instant::now();
+                debug!("resp: {:?}", resp);
+                observe_cert_expiry(&chain_sender.chain_name, &resp);
+                observe_date_skew(&chain_sender.chain_name, &resp);
+                match resp.json::<Value>().await {
+                    Ok(resp) => {
+                        info!("Call '{}': {:?}", &record.api, resp);
+                        record.add_resp(resp);
+                    }
+                    Err(e) => {
+                        error!("decoding resp from '{}' failed: {}", &record.api, e);
+                        record.add_error(&e);
+                    }
+                }
+                record.phases.body = Some(elapsed_ms(start));
+            }
+            Err(e) => {
+                error!("Call '{}' failed: {}", &record.api, e);
+                record.add_error(&e);
+            }
+        }
+    }
+}
+
+impl Probe for AutoTxProbe {
+    async fn submit(&self, chain_sender: &ChainSender, record: &mut Record) -> Option<String> {
+        let request_key = record.timestamp.to_string();
+        let user_code = record.user_code.clone();
+        self.send_and_decode(
+            chain_sender,
+            record,
+            &[("request_key", &request_key), ("user_code", &user_code)],
+        )
+        .await;
+        (self.classify(record) == Outcome::Succeed)
+            .then(|| record.resp["data"]["hash"].to_string().replace('\"', ""))
+    }
+
+    async fn verify(&self, chain_sender: &ChainSender, utx: &UnverifiedTX, record: &mut Record) {
+        let request_key = utx.sent_timestamp.to_string();
+        let user_code = record.user_code.clone();
+        self.send_and_decode(
+            chain_sender,
+            record,
+            &[("request_key", &request_key), ("user_code", &user_code)],
+        )
+        .await;
+    }
+
+    fn classify(&self, record: &Record) -> Outcome {
+        if record.status == 200 {
+            Outcome::Succeed
+        } else {
+            Outcome::Failed
+        }
+    }
+}
+
+/// Probe calling a read-only endpoint, there is nothing to verify afterwards.
+#[derive(Debug, Clone)]
+pub struct QueryCallProbe {
+    pub http_clients: Arc<HttpClients>,
+    pub query: QueryProbe,
+}
+
+impl QueryCallProbe {
+    fn check(&self, value: &Value) -> bool {
+        let non_empty = match value {
+            Value::Null => false,
+            Value::String(s) => !s.is_empty(),
+            Value::Array(a) => !a.is_empty(),
+            Value::Object(o) => !o.is_empty(),
+            _ => true,
+        };
+        if !non_empty {
+            return false;
+        }
+        if !self.query.numeric && self.query.min.is_none() && self.query.max.is_none() {
+            return true;
+        }
+        match as_number(value) {
+            Some(n) => {
+                self.query.min.is_none_or(|min| n >= min)
+                    && self.query.max.is_none_or(|max| n <= max)
+            }
+            None => false,
+        }
+    }
+}
+
+impl Probe for QueryCallProbe {
+    async fn submit(&self, chain_sender: &ChainSender, record: &mut Record) -> Option<String> {
+        let request = match self
+            .http_clients
+            .request(
+                chain_sender,
+                &record.api,
+                &record.data,
+                &[("user_code", &record.user_code)],
+            )
+            .await
+        {
+            Ok(request) => request,
+            Err(e) => {
+                error!("{}", e);
+                record.error = Some(ErrorKind::Other);
+                return None;
+            }
+        };
+        match request.send().await {
+            Ok(resp) => {
+                observe_cert_expiry(&chain_sender.chain_name, &resp);
+                observe_date_skew(&chain_sender.chain_name, &resp);
+                let status = resp.status().as_u16();
+                match resp.json::<Value>().await {
+                    Ok(resp) => {
+                        info!("Query '{}': {:?}", &record.api, resp);
+                        record.status = status;
+                        record.resp = resp;
+                    }
+                    Err(e) => {
+                        error!("decoding resp from '{}' failed: {}", &record.api, e);
+                        record.add_error(&e);
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Call '{}' failed: {}", &record.api, e);
+                record.add_error(&e);
+            }
+        }
+        None
+    }
+
+    async fn verify(&self, _chain_sender: &ChainSender, _utx: &UnverifiedTX, _record: &mut Record) {
+    }
+
+    fn classify(&self, record: &Record) -> Outcome {
+        let value = if self.query.pointer.is_empty() {
+            Some(&record.resp)
+        } else {
+            record.resp.pointer(&self.query.pointer)
+        };
+        match value {
+            Some(value) if record.status == 200 && self.check(value) => Outcome::Succeed,
+            _ => Outcome::Failed,
+        }
+    }
+}
+
+/// Answers with the statuses queued in it, 200 once they run out.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct FakeProbe {
+    pub submit_statuses: parking_lot::Mutex<std::collections::VecDeque<u16>>,
+    pub verify_statuses: parking_lot::Mutex<std::collections::VecDeque<u16>>,
+}
+
+#[cfg(test)]
+impl Probe for FakeProbe {
+    async fn submit(&self, _chain_sender: &ChainSender, record: &mut Record) -> Option<String> {
+        record.status = self.submit_statuses.lock().pop_front().unwrap_or(200);
+        if record.status != 200 {
+            record.error = Some(ErrorKind::Connect);
+        }
+        (record.status == 200).then(|| format!("0x{}{}", record.user_code, record.timestamp))
+    }
+
+    async fn verify(&self, _chain_sender: &ChainSender, _utx: &UnverifiedTX, record: &mut Record) {
+        record.status = self.verify_statuses.lock().pop_front().unwrap_or(200);
+        if record.status != 200 {
+            record.error = Some(ErrorKind::Connect);
+        }
+    }
+
+    fn classify(&self, record: &Record) -> Outcome {
+        if record.status == 200 {
+            Outcome::Succeed
+        } else {
+            Outcome::Failed
+        }
+    }
+}
+
+/// Accept JSON numbers, decimal strings and "0x" prefixed hex strings
+pub fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u128::from_str_radix(hex, 16).ok().map(|n| n as f64),
+            None => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::{config::AvailabilityRule, phase::Phases};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use storage_dal::StorageData;
@@ -21,17 +22,75 @@ pub struct Record {
     /// Units in ms
     pub timestamp: u64,
     pub api: String,
+    /// Name of the payload
+    #[serde(default)]
+    pub payload: String,
     pub data: String,
     pub user_code: String,
     pub resp: Value,
     pub status: u16,
+    #[serde(default)]
+    pub attempts: u8,
+    /// Why the last attempt failed
+    #[serde(default)]
+    pub error: Option<ErrorKind>,
+    /// Of the last attempt
+    #[serde(default)]
+    pub phases: Phases,
 }
 
 impl Record {
+    /// Keep `resp`, its `code` is the status, a response without a numeric
+    /// one fails to decode
     pub fn add_resp(&mut self, resp: Value) {
-        self.status = resp["code"].as_u64().unwrap() as u16;
+        match resp["code"]
+            .as_u64()
+            .and_then(|code| u16::try_from(code).ok())
+        {
+            Some(code) => self.status = code,
+            None => self.error = Some(ErrorKind::Decode),
+        }
         self.resp = resp;
     }
+
+    pub fn add_error(&mut self, error: &reqwest::Error) {
+        self.error = Some(error.into());
+    }
+
+    /// Clear the response of the former attempt
+    pub fn next_attempt(&mut self) {
+        self.attempts += 1;
+        self.resp = Value::Null;
+        self.status = 0;
+        self.error = None;
+        self.phases = Phases::default();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Connect,
+    Timeout,
+    /// The response is not the expected JSON
+    Decode,
+    /// The response is decoded but not classified as succeed
+    Status,
+    Other,
+}
+
+impl From<&reqwest::Error> for ErrorKind {
+    fn from(error: &reqwest::Error) -> Self {
+        if error.is_timeout() {
+            Self::Timeout
+        } else if error.is_connect() {
+            Self::Connect
+        } else if error.is_decode() {
+            Self::Decode
+        } else {
+            Self::Other
+        }
+    }
 }
 
 #[derive(StorageData, Debug, Clone, Default, Deserialize, Serialize)]
@@ -41,6 +100,12 @@ pub struct UnverifiedTX {
     /// Units in ms
     pub sent_timestamp: u64,
     pub user_code: String,
+    /// Name of the payload
+    #[serde(default)]
+    pub payload: String,
+    /// Sent only after retries
+    #[serde(default)]
+    pub retried: bool,
 }
 
 impl UnverifiedTX {
@@ -54,24 +119,91 @@ impl UnverifiedTX {
 
 #[derive(StorageData, Debug, Clone, Default, Deserialize, Serialize)]
 pub struct VerifiedResult {
-    /// Units in minutes
+    /// Units in buckets of `bucket_secs`
     pub timestamp: u64,
+    /// Units in second, results stored before it was configurable are in minutes
+    #[serde(default = "default_bucket_secs")]
+    pub bucket_secs: u64,
     pub chain_name: String,
-    pub sent_num: u8,
-    pub sent_failed_num: u8,
-    pub failed_num: u8,
-    pub succeed_num: u8,
+    pub sent_num: u32,
+    pub sent_failed_num: u32,
+    pub failed_num: u32,
+    pub succeed_num: u32,
+    /// Succeeded txs sent or verified only after retries, these count as failed
+    /// in the strict SLA
+    #[serde(default)]
+    pub retried_num: u32,
+    /// Nothing was probed in the bucket, recorded when the gap is found
+    #[serde(default)]
+    pub no_data: bool,
 }
 
 impl VerifiedResult {
-    pub const fn new(timestamp: u64, chain_name: String) -> Self {
+    pub const fn new(timestamp: u64, bucket_secs: u64, chain_name: String) -> Self {
         Self {
             timestamp,
+            bucket_secs,
             sent_num: 0,
             sent_failed_num: 0,
             failed_num: 0,
             succeed_num: 0,
+            retried_num: 0,
+            no_data: false,
             chain_name,
         }
     }
+
+    /// Units in second, when the bucket ends
+    pub const fn end_secs(&self) -> u64 {
+        (self.timestamp + 1) * self.bucket_secs
+    }
+
+    /// Every probe of the bucket, sent or not
+    pub const fn samples(&self) -> u64 {
+        self.sent_num as u64 + self.sent_failed_num as u64
+    }
+
+    /// Whether the bucket is available and whether it is without any retry,
+    /// `None` if it is excluded by `rule`
+    pub fn availability(&self, rule: &AvailabilityRule) -> Option<(bool, bool)> {
+        if self.no_data {
+            return rule.no_data.classify();
+        }
+        let samples = self.samples();
+        if samples == 0 || samples < rule.min_samples {
+            return rule.sparse_minute.classify();
+        }
+        let succeed = self.succeed_num as f64;
+        let strictly_succeed = self.succeed_num.saturating_sub(self.retried_num) as f64;
+        Some((
+            succeed >= rule.min_success_ratio * samples as f64,
+            strictly_succeed >= rule.min_success_ratio * samples as f64,
+        ))
+    }
+}
+
+const fn default_bucket_secs() -> u64 {
+    60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn code_of_the_response_is_the_status() {
+        let mut record = Record::default();
+        record.add_resp(json!({"code": 200, "data": {}}));
+        assert_eq!((record.status, record.error), (200, None));
+    }
+
+    #[test]
+    fn response_without_a_numeric_code_fails_to_decode() {
+        for resp in [json!({"error": "unauthorized"}), json!({"code": "401"})] {
+            let mut record = Record::default();
+            record.add_resp(resp);
+            assert_eq!((record.status, record.error), (0, Some(ErrorKind::Decode)));
+        }
+    }
 }
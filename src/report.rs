@@ -0,0 +1,171 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::Config,
+    finalizer::Watermark,
+    metrics::{stored_vrs, SeriesRule},
+    record::VerifiedResult,
+    time::{days_window, get_readable_time_from_bucket, month_of, month_window},
+};
+use color_eyre::eyre::{eyre, Result};
+use storage_dal::Storage;
+
+/// The counted buckets of a series in a calendar month
+#[derive(Debug, Clone, Default)]
+struct MonthReport {
+    observed: u64,
+    unavailable: u64,
+    strict_unavailable: u64,
+    sent_failed: u64,
+    no_data: u64,
+}
+
+impl MonthReport {
+    fn ratio(&self, unavailable: u64) -> f64 {
+        if self.observed == 0 {
+            return 1.0;
+        }
+        1.0 - unavailable as f64 / self.observed as f64
+    }
+}
+
+/// The configured series of the chains in `chains`, or of all chains if it is empty
+fn selected_series(config: &Config, chains: &[String]) -> Vec<String> {
+    config
+        .chain_sender_vec
+        .iter()
+        .filter(|chain_sender| chains.is_empty() || chains.contains(&chain_sender.chain_name))
+        .flat_map(|chain_sender| chain_sender.series_names())
+        .collect()
+}
+
+/// Parse `month` (YYYY-MM), the current month of the series if it is `None`
+fn parse_month(month: Option<&str>, series_rule: &SeriesRule, now: u64) -> Result<(i32, u32)> {
+    let Some(month) = month else {
+        return Ok(month_of(now / 1000, series_rule.timezone));
+    };
+    month
+        .split_once('-')
+        .and_then(|(year, month)| Some((year.parse().ok()?, month.parse().ok()?)))
+        .filter(|(_, month)| (1..=12).contains(month))
+        .ok_or_else(|| eyre!("invalid month '{}', expected YYYY-MM", month))
+}
+
+/// Print the availability of every selected series in `month`, counting the
+/// buckets up to the watermark like the metrics do
+pub fn report(
+    storage: &Storage,
+    config: &Config,
+    month: Option<&str>,
+    chains: &[String],
+    now: u64,
+) -> Result<()> {
+    for name in selected_series(config, chains) {
+        let series_rule = SeriesRule::of(config, &name);
+        let (year, month) = parse_month(month, &series_rule, now)?;
+        let (start, end) = month_window(year, month, series_rule.timezone)
+            .ok_or_else(|| eyre!("invalid month {}-{:02}", year, month))?;
+        let watermark_end = storage
+            .get::<Watermark>(&name)
+            .map_or(0, |watermark| watermark.end_secs());
+        let mut month_report = MonthReport::default();
+        for vr in stored_vrs(storage, &name) {
+            let vr_start = vr.timestamp * vr.bucket_secs;
+            if vr_start < start || vr_start >= end || vr.end_secs() > watermark_end {
+                continue;
+            }
+            if vr.no_data {
+                month_report.no_data += 1;
+            }
+            let Some((available, strictly_available)) = series_rule.availability(storage, &vr)
+            else {
+                continue;
+            };
+            month_report.observed += 1;
+            if !strictly_available {
+                month_report.strict_unavailable += 1;
+            }
+            if !available {
+                month_report.unavailable += 1;
+                if vr.sent_failed_num != 0 {
+                    month_report.sent_failed += 1;
+                }
+            }
+        }
+        println!(
+            "{} {}-{:02}: availability {:.4}%, strict availability {:.4}%, observed: {}, unavailable: {}, strict_unavailable: {}, sent_failed: {}, no_data: {}",
+            name,
+            year,
+            month,
+            month_report.ratio(month_report.unavailable) * 100.0,
+            month_report.ratio(month_report.strict_unavailable) * 100.0,
+            month_report.observed,
+            month_report.unavailable,
+            month_report.strict_unavailable,
+            month_report.sent_failed,
+            month_report.no_data,
+        );
+    }
+    Ok(())
+}
+
+/// Print the stored buckets of every selected series between the days `from`
+/// and `to` in the timezone of the series, as json lines or csv
+pub fn export(
+    storage: &Storage,
+    config: &Config,
+    from: &str,
+    to: &str,
+    chains: &[String],
+    csv: bool,
+) -> Result<()> {
+    if csv {
+        println!("chain_name,time,timestamp,bucket_secs,sent_num,sent_failed_num,failed_num,succeed_num,retried_num,no_data");
+    }
+    for name in selected_series(config, chains) {
+        let series_rule = SeriesRule::of(config, &name);
+        let (start, end) = days_window(from, to, series_rule.timezone)
+            .ok_or_else(|| eyre!("invalid days '{}' to '{}', expected YYYY-MM-DD", from, to))?;
+        let mut vrs: Vec<VerifiedResult> = stored_vrs(storage, &name)
+            .into_iter()
+            .filter(|vr| (start..end).contains(&(vr.timestamp * vr.bucket_secs)))
+            .collect();
+        vrs.sort_by_key(|vr| vr.timestamp);
+        for vr in vrs {
+            if csv {
+                println!(
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    vr.chain_name,
+                    get_readable_time_from_bucket(
+                        vr.timestamp,
+                        vr.bucket_secs,
+                        series_rule.timezone
+                    ),
+                    vr.timestamp,
+                    vr.bucket_secs,
+                    vr.sent_num,
+                    vr.sent_failed_num,
+                    vr.failed_num,
+                    vr.succeed_num,
+                    vr.retried_num,
+                    vr.no_data
+                );
+            } else {
+                println!("{}", serde_json::to_string(&vr)?);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,118 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::{ChainSender, Scenario, ScenarioStep},
+    http::{observe_cert_expiry, HttpClients},
+    skew::observe_date_skew,
+    template::render,
+};
+use color_eyre::eyre::{bail, eyre, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio::time::{sleep, Duration, Instant};
+
+/// Run the steps of `scenario` in order, returns whether all of them passed.
+pub async fn run(
+    http_clients: &HttpClients,
+    chain_sender: &ChainSender,
+    scenario: &Scenario,
+) -> bool {
+    let mut vars = chain_sender.template_vars();
+    for (name, value) in &scenario.vars {
+        let value = render(value, &vars);
+        vars.insert(name.clone(), value);
+    }
+    for step in &scenario.step_vec {
+        let deadline = Instant::now() + Duration::from_secs(step.wait);
+        loop {
+            match run_step(http_clients, chain_sender, step, &vars).await {
+                Ok(captured) => {
+                    vars.extend(captured);
+                    break;
+                }
+                Err(e) if Instant::now() >= deadline => {
+                    warn!(
+                        "scenario '{}' step '{}' failed: {}",
+                        scenario.name, step.name, e
+                    );
+                    return false;
+                }
+                Err(e) => {
+                    debug!(
+                        "scenario '{}' step '{}' not passed yet: {}",
+                        scenario.name, step.name, e
+                    );
+                    sleep(Duration::from_secs(step.retry_interval)).await;
+                }
+            }
+        }
+    }
+    info!(
+        "scenario '{}' of '{}' passed",
+        scenario.name, chain_sender.chain_name
+    );
+    true
+}
+
+async fn run_step(
+    http_clients: &HttpClients,
+    chain_sender: &ChainSender,
+    step: &ScenarioStep,
+    vars: &HashMap<String, String>,
+) -> Result<HashMap<String, String>> {
+    let url = render(&step.url, vars);
+    let body = render(&step.body, vars);
+    let resp = http_clients
+        .request(
+            chain_sender,
+            &url,
+            &body,
+            &[("user_code", &chain_sender.user_code)],
+        )
+        .await?
+        .send()
+        .await?;
+    observe_cert_expiry(&chain_sender.chain_name, &resp);
+    observe_date_skew(&chain_sender.chain_name, &resp);
+    let status = resp.status();
+    let resp = resp.json::<Value>().await?;
+    debug!("Step '{}' '{}': {:?}", step.name, url, resp);
+    if !status.is_success() {
+        bail!("status {}: {}", status, resp);
+    }
+
+    for (pointer, expected) in &step.expect {
+        let expected = render(expected, vars);
+        match resp.pointer(pointer).map(value_to_string) {
+            Some(value) if value == expected => {}
+            value => bail!("'{}' expected '{}', got {:?}", pointer, expected, value),
+        }
+    }
+    step.capture
+        .iter()
+        .map(|(var, pointer)| {
+            resp.pointer(pointer)
+                .map(|value| (var.clone(), value_to_string(value)))
+                .ok_or_else(|| eyre!("'{}' not found in {}", pointer, resp))
+        })
+        .collect()
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
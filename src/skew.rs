@@ -0,0 +1,146 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    clock,
+    config::{Config, SkewConfig},
+    metrics::{CLOCK_SKEW, CLOCK_SKEW_EXCEEDED},
+};
+use chrono::DateTime;
+use color_eyre::eyre::{eyre, Result};
+use parking_lot::{Mutex, RwLock};
+use reqwest::{header::DATE, Response};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    net::UdpSocket,
+    time::{interval, timeout},
+};
+
+/// Units in ms, older measurements are not trusted any more
+const SKEW_TTL: u64 = 600_000;
+
+/// Seconds between the NTP epoch 1900 and the unix epoch
+const NTP_UNIX_OFFSET: f64 = 2_208_988_800.0;
+
+/// The latest skew of the local clock to the SNTP server, units in second,
+/// together with when it was measured in ms
+static SNTP_SKEW: Mutex<Option<(f64, u64)>> = Mutex::new(None);
+
+/// Compare the local clock with the `Date` header of `resp`, which has a
+/// resolution of one second. Only exported as the skew to `source`, a single
+/// gateway with a bad clock must not hold the finalization of every chain.
+pub fn observe_date_skew(source: &str, resp: &Response) {
+    let Some(date) = resp
+        .headers()
+        .get(DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+    else {
+        return;
+    };
+    let server_ms = date.timestamp_millis() + 500;
+    CLOCK_SKEW
+        .with_label_values(&[source])
+        .set((clock::now() as i64 - server_ms) as f64 / 1000.0);
+}
+
+/// The recent skew to the SNTP server if it is beyond `max_skew`. Units in second
+pub fn exceeded(skew_config: &SkewConfig) -> Option<f64> {
+    let now = clock::now();
+    let skew = SNTP_SKEW
+        .lock()
+        .filter(|(_, measured_at)| now.saturating_sub(*measured_at) <= SKEW_TTL)
+        .map(|(skew, _)| skew)
+        .filter(|skew| skew.abs() > skew_config.max_skew as f64);
+    CLOCK_SKEW_EXCEEDED.set(i64::from(skew.is_some()));
+    skew
+}
+
+/// Measure the skew to `sntp_server` every `sntp_interval` if it is configured
+pub async fn run_sntp(config: Arc<RwLock<Config>>) {
+    let mut ticker = interval(Duration::from_secs(1));
+    // Units in ms, when the server was last queried
+    let mut queried_at: Option<u64> = None;
+    loop {
+        ticker.tick().await;
+        // Both may be changed by a reload
+        let (server, sntp_interval) = {
+            let skew = &config.read().skew;
+            (skew.sntp_server.clone(), skew.sntp_interval)
+        };
+        if server.is_empty() {
+            continue;
+        }
+        let now = clock::now();
+        if queried_at.is_some_and(|at| now.saturating_sub(at) < sntp_interval * 1000) {
+            continue;
+        }
+        queried_at = Some(now);
+        match sntp_skew(&server).await {
+            Ok(skew) => {
+                debug!("clock skew to '{}': {}s", server, skew);
+                CLOCK_SKEW.with_label_values(&["sntp"]).set(skew);
+                *SNTP_SKEW.lock() = Some((skew, clock::now()));
+            }
+            Err(e) => warn!("sntp query to '{}' failed: {}", server, e),
+        }
+    }
+}
+
+/// The skew of the local clock to an SNTP server, units in second
+async fn sntp_skew(server: &str) -> Result<f64> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(server).await?;
+    // LI 0, version 3, mode 3 (client)
+    let mut request = [0u8; 48];
+    request[0] = 0x1b;
+    let originate = clock::now() as f64 / 1000.0;
+    socket.send(&request).await?;
+    let mut response = [0u8; 48];
+    let len = timeout(Duration::from_secs(5), socket.recv(&mut response))
+        .await
+        .map_err(|_| eyre!("timeout"))??;
+    let destination = clock::now() as f64 / 1000.0;
+    if len < 48 {
+        return Err(eyre!("short response of {} bytes", len));
+    }
+    if response[0] >> 6 == 3 {
+        return Err(eyre!("the server is not synchronized"));
+    }
+    if response[0] & 0x07 != 4 {
+        return Err(eyre!(
+            "mode {} is not a server response",
+            response[0] & 0x07
+        ));
+    }
+    if response[1] == 0 {
+        return Err(eyre!(
+            "kiss-o'-death '{}'",
+            String::from_utf8_lossy(&response[12..16])
+        ));
+    }
+    if response[40..48].iter().all(|&byte| byte == 0) {
+        return Err(eyre!("no transmit timestamp"));
+    }
+    let receive = ntp_to_unix(&response[32..40]);
+    let transmit = ntp_to_unix(&response[40..48]);
+    // The offset of the server to the local clock is ((t2 - t1) + (t3 - t4)) / 2
+    Ok(-((receive - originate) + (transmit - destination)) / 2.0)
+}
+
+fn ntp_to_unix(timestamp: &[u8]) -> f64 {
+    let seconds = u32::from_be_bytes([timestamp[0], timestamp[1], timestamp[2], timestamp[3]]);
+    let fraction = u32::from_be_bytes([timestamp[4], timestamp[5], timestamp[6], timestamp[7]]);
+    seconds as f64 + fraction as f64 / 4_294_967_296.0 - NTP_UNIX_OFFSET
+}
@@ -0,0 +1,86 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::clock;
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Render the `{{...}}` placeholders of `template`.
+///
+/// `vars` take precedence over the builtins:
+/// - `timestamp`: unix time in ms
+/// - `uuid`: a random uuid v4
+/// - `counter`: increased on every render since start
+/// - `random_hex` or `random_hex:<bytes>`: random bytes in hex without "0x", 32 by default
+/// - `env:<NAME>`: the environment variable `NAME`
+///
+/// `timestamp`, `uuid` and `counter` keep the same value within one render,
+/// unknown placeholders are left as is.
+pub fn render(template: &str, vars: &HashMap<String, String>) -> String {
+    let mut timestamp = None;
+    let mut uuid = None;
+    let mut counter = None;
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..start + len + 4];
+        let key = placeholder[2..placeholder.len() - 2].trim();
+        rest = &rest[start + len + 4..];
+
+        if let Some(value) = vars.get(key) {
+            rendered.push_str(value);
+            continue;
+        }
+        match key.split_once(':').unwrap_or((key, "")) {
+            ("timestamp", _) => {
+                rendered.push_str(timestamp.get_or_insert_with(|| clock::now().to_string()))
+            }
+            ("uuid", _) => {
+                rendered.push_str(uuid.get_or_insert_with(|| uuid::Uuid::new_v4().to_string()))
+            }
+            ("counter", _) => rendered.push_str(
+                counter.get_or_insert_with(|| COUNTER.fetch_add(1, Ordering::Relaxed).to_string()),
+            ),
+            ("random_hex", len) => rendered.push_str(&random_hex(len.parse().unwrap_or(32))),
+            ("env", name) if !name.is_empty() => match std::env::var(name) {
+                Ok(value) => rendered.push_str(&value),
+                Err(e) => {
+                    warn!("render '{}' failed: {}", placeholder, e);
+                    rendered.push_str(placeholder);
+                }
+            },
+            _ => rendered.push_str(placeholder),
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn random_hex(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len).fold(String::with_capacity(len * 2), |mut hex, _| {
+        hex.push_str(&format!("{:02x}", rng.gen::<u8>()));
+        hex
+    })
+}
@@ -13,24 +13,69 @@
 // limitations under the License.
 
 use chrono::prelude::*;
+use chrono_tz::Tz;
 
 pub fn unix_now() -> u64 {
     let d = ::std::time::UNIX_EPOCH.elapsed().unwrap();
     d.as_secs() * 1_000 + u64::from(d.subsec_millis())
 }
 
-pub fn ms_to_minute_scale(ms: u64) -> u64 {
-    (ms as f64 / 1_000.0 / 60.0).trunc() as u64
+/// Units of the bucket in `bucket_secs`
+pub const fn ms_to_bucket(ms: u64, bucket_secs: u64) -> u64 {
+    ms / 1_000 / bucket_secs
 }
 
-pub fn get_latest_finalized_minute(time: u64, check_timeout: u64) -> u64 {
-    ms_to_minute_scale(time - (check_timeout * 1000)) - 1
+pub const fn get_latest_finalized_bucket(time: u64, check_timeout: u64, bucket_secs: u64) -> u64 {
+    ms_to_bucket(time - (check_timeout * 1000), bucket_secs) - 1
 }
 
-pub fn get_readable_time_from_minute(timestamp: u64) -> String {
-    Utc.timestamp_opt((timestamp * 60) as i64, 0)
+pub fn get_readable_time_from_bucket(bucket: u64, bucket_secs: u64, timezone: Tz) -> String {
+    Utc.timestamp_opt((bucket * bucket_secs) as i64, 0)
         .unwrap()
-        .with_timezone(&FixedOffset::east_opt(8 * 3600).unwrap())
-        .format("%Y-%m-%d %H:%M")
+        .with_timezone(&timezone)
+        .format(if bucket_secs.is_multiple_of(60) {
+            "%Y-%m-%d %H:%M"
+        } else {
+            "%Y-%m-%d %H:%M:%S"
+        })
         .to_string()
 }
+
+/// The calendar month of `secs` in `timezone`, as (year, month)
+pub fn month_of(secs: u64, timezone: Tz) -> (i32, u32) {
+    let time = Utc
+        .timestamp_opt(secs as i64, 0)
+        .unwrap()
+        .with_timezone(&timezone);
+    (time.year(), time.month())
+}
+
+/// Units in second, the start and the end of the calendar month in `timezone`
+pub fn month_window(year: i32, month: u32, timezone: Tz) -> Option<(u64, u64)> {
+    let start_of = |year, month| {
+        timezone
+            .with_ymd_and_hms(year, month, 1, 0, 0, 0)
+            .earliest()
+            .map(|time| time.timestamp() as u64)
+    };
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    Some((start_of(year, month)?, start_of(next_year, next_month)?))
+}
+
+/// Units in second, from the start of the day `from` to the end of the day
+/// `to` (YYYY-MM-DD) in `timezone`
+pub fn days_window(from: &str, to: &str, timezone: Tz) -> Option<(u64, u64)> {
+    let start_of = |date: NaiveDate| {
+        date.and_hms_opt(0, 0, 0)?
+            .and_local_timezone(timezone)
+            .earliest()
+            .map(|time| time.timestamp() as u64)
+    };
+    let from = NaiveDate::parse_from_str(from, "%Y-%m-%d").ok()?;
+    let to = NaiveDate::parse_from_str(to, "%Y-%m-%d").ok()?;
+    Some((start_of(from)?, start_of(to.succ_opt()?)?))
+}